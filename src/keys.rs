@@ -0,0 +1,319 @@
+use crate::{generate_key, pointer_starts_with, storage::Storage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    error::Error,
+    net::IpAddr,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Scope {
+    #[serde(rename = "admin")]
+    Admin,
+    #[serde(rename = "data:read")]
+    DataRead,
+    #[serde(rename = "data:write")]
+    DataWrite,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PathRule {
+    pub prefix: String,
+    pub methods: Vec<String>,
+}
+
+pub const EXPIRY_WARNING: i64 = 7 * 24 * 60 * 60;
+pub const MAX_FAILED_ATTEMPTS: u32 = 5;
+pub const LOCKOUT_DURATION: Duration = Duration::from_secs(15 * 60);
+pub const EXPIRY_WARNING_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+static EXPIRY_WARNINGS: LazyLock<Mutex<HashMap<String, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RetiredKey {
+    pub hash: String,
+    pub expires: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    pub name: String,
+    pub hash: String,
+    pub scopes: Vec<Scope>,
+    pub created: i64,
+    #[serde(default)]
+    pub paths: Option<Vec<PathRule>>,
+    #[serde(default)]
+    pub expires: Option<i64>,
+    #[serde(default)]
+    pub previous: Vec<RetiredKey>,
+}
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+
+    pub fn allows(&self, method: &str, pointer: &str) -> bool {
+        match &self.paths {
+            None => true,
+            Some(rules) => rules.iter().any(|rule| {
+                pointer_starts_with(pointer, &rule.prefix)
+                    && rule.methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+            }),
+        }
+    }
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Default)]
+pub struct AuthFailures {
+    attempts: Mutex<HashMap<IpAddr, (u32, Instant)>>,
+}
+
+impl AuthFailures {
+    pub fn locked_out(&self, ip: IpAddr) -> Option<Duration> {
+        let mut attempts = self.attempts.lock().ok()?;
+        let (count, last) = *attempts.get(&ip)?;
+        let elapsed = last.elapsed();
+        if elapsed >= LOCKOUT_DURATION {
+            attempts.remove(&ip);
+            return None;
+        }
+        (count >= MAX_FAILED_ATTEMPTS).then(|| LOCKOUT_DURATION - elapsed)
+    }
+
+    pub fn record(&self, ip: IpAddr) {
+        if let Ok(mut attempts) = self.attempts.lock() {
+            let entry = attempts.entry(ip).or_insert((0, Instant::now()));
+            if entry.1.elapsed() >= LOCKOUT_DURATION {
+                entry.0 = 0;
+            }
+            entry.0 += 1;
+            entry.1 = Instant::now();
+            if entry.0 == MAX_FAILED_ATTEMPTS {
+                println!("Locking out {} after {} failed auth attempts", ip, entry.0);
+            }
+        }
+    }
+
+    pub fn clear(&self, ip: IpAddr) {
+        if let Ok(mut attempts) = self.attempts.lock() {
+            attempts.remove(&ip);
+        }
+    }
+}
+
+fn should_warn_expiry(name: &str) -> bool {
+    let Ok(mut warnings) = EXPIRY_WARNINGS.lock() else {
+        return false;
+    };
+    if warnings
+        .get(name)
+        .is_some_and(|last| last.elapsed() < EXPIRY_WARNING_INTERVAL)
+    {
+        return false;
+    }
+    warnings.insert(name.to_string(), Instant::now());
+    true
+}
+
+pub fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+pub fn load_keys(storage: &dyn Storage) -> Result<Vec<ApiKey>, Box<dyn Error>> {
+    match storage.load("keys")? {
+        Some(keys) => Ok(serde_json::from_value(keys)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub fn save_keys(storage: &dyn Storage, keys: &[ApiKey]) -> Result<(), Box<dyn Error>> {
+    storage.save("keys", &json!(keys))
+}
+
+fn now() -> i64 {
+    let now: DateTime<Utc> = SystemTime::now().into();
+    now.timestamp()
+}
+
+pub fn authenticate(storage: &dyn Storage, key: &str) -> Result<Option<ApiKey>, Box<dyn Error>> {
+    let hash = hash_key(key);
+    let now = now();
+    for api_key in load_keys(storage)? {
+        let expires = if constant_time_eq(api_key.hash.as_bytes(), hash.as_bytes()) {
+            api_key.expires
+        } else if let Some(retired) = api_key
+            .previous
+            .iter()
+            .find(|r| constant_time_eq(r.hash.as_bytes(), hash.as_bytes()))
+        {
+            Some(retired.expires)
+        } else {
+            continue;
+        };
+        match expires {
+            Some(expires) if expires <= now => return Ok(None),
+            Some(expires)
+                if expires - now < EXPIRY_WARNING && should_warn_expiry(&api_key.name) =>
+            {
+                println!(
+                    "API key {} used {} hours before it expires",
+                    api_key.name,
+                    (expires - now) / 3600
+                )
+            }
+            _ => {}
+        }
+        return Ok(Some(api_key));
+    }
+    Ok(None)
+}
+
+pub fn create_key(
+    storage: &dyn Storage,
+    name: &str,
+    scopes: Vec<Scope>,
+    paths: Option<Vec<PathRule>>,
+    expires: Option<i64>,
+) -> Result<String, Box<dyn Error>> {
+    let mut keys = load_keys(storage)?;
+    if keys.iter().any(|k| k.name == name) {
+        return Err(format!("key {} already exists", name).into());
+    }
+    let key = generate_key()?;
+    keys.push(ApiKey {
+        name: name.to_string(),
+        hash: hash_key(&key),
+        scopes,
+        created: now(),
+        paths,
+        expires,
+        previous: Vec::new(),
+    });
+    save_keys(storage, &keys)?;
+    Ok(key)
+}
+
+pub fn import_key(
+    storage: &dyn Storage,
+    name: &str,
+    key: &str,
+    scopes: Vec<Scope>,
+) -> Result<(), Box<dyn Error>> {
+    let mut keys = load_keys(storage)?;
+    let hash = hash_key(key);
+    if keys.iter().any(|k| k.hash == hash) {
+        return Ok(());
+    }
+    if let Some(existing) = keys.iter_mut().find(|k| k.name == name) {
+        existing.hash = hash;
+        existing.scopes = scopes;
+        existing.created = now();
+        println!("Replaced key {} with the configured api_key", name);
+        return save_keys(storage, &keys);
+    }
+    keys.push(ApiKey {
+        name: name.to_string(),
+        hash,
+        scopes,
+        created: now(),
+        paths: None,
+        expires: None,
+        previous: Vec::new(),
+    });
+    save_keys(storage, &keys)
+}
+
+pub fn revoke_key(storage: &dyn Storage, name: &str) -> Result<bool, Box<dyn Error>> {
+    let mut keys = load_keys(storage)?;
+    let count = keys.len();
+    keys.retain(|k| k.name != name);
+    if keys.len() == count {
+        return Ok(false);
+    }
+    save_keys(storage, &keys)?;
+    Ok(true)
+}
+
+pub fn rotate_key(
+    storage: &dyn Storage,
+    name: &str,
+    grace_period: i64,
+    expires: Option<i64>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let mut keys = load_keys(storage)?;
+    let Some(api_key) = keys.iter_mut().find(|k| k.name == name) else {
+        return Ok(None);
+    };
+    let key = generate_key()?;
+    let now = now();
+    api_key.previous.retain(|r| r.expires > now);
+    let retired_expiry = match api_key.expires {
+        Some(expires) => expires.min(now + grace_period),
+        None => now + grace_period,
+    };
+    api_key.previous.push(RetiredKey {
+        hash: std::mem::replace(&mut api_key.hash, hash_key(&key)),
+        expires: retired_expiry,
+    });
+    if expires.is_some() {
+        api_key.expires = expires;
+    }
+    save_keys(storage, &keys)?;
+    Ok(Some(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_time_eq_compares_contents_and_length() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"s"));
+    }
+
+    #[test]
+    fn failed_auth_locks_out_after_max_attempts() {
+        let failures = AuthFailures::default();
+        let ip: IpAddr = "192.0.2.10".parse().unwrap();
+        for _ in 1..MAX_FAILED_ATTEMPTS {
+            failures.record(ip);
+            assert!(failures.locked_out(ip).is_none());
+        }
+        failures.record(ip);
+        let remaining = failures.locked_out(ip).unwrap();
+        assert!(remaining <= LOCKOUT_DURATION);
+        assert!(failures.locked_out("192.0.2.11".parse().unwrap()).is_none());
+        failures.clear(ip);
+        assert!(failures.locked_out(ip).is_none());
+    }
+
+    #[test]
+    fn lockouts_are_per_instance() {
+        let ip: IpAddr = "192.0.2.10".parse().unwrap();
+        let failures = AuthFailures::default();
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            failures.record(ip);
+        }
+        assert!(failures.locked_out(ip).is_some());
+        assert!(AuthFailures::default().locked_out(ip).is_none());
+    }
+}
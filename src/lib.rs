@@ -1,3 +1,13 @@
+pub mod keys;
+pub mod nicknames;
+pub mod protocol;
+pub mod ratelimit;
+pub mod sessions;
+pub mod slp;
+pub mod status;
+pub mod storage;
+pub mod tls;
+
 use api_key::{
     self,
     types::{ApiKeyResults, Default, StringGenerator},
@@ -5,58 +15,283 @@ use api_key::{
 use base64::{Engine, prelude::BASE64_STANDARD};
 use chrono::{DateTime, Utc};
 use image;
+use keys::{AuthFailures, Scope};
 use magick_rust::{MagickWand, PixelWand, magick_wand_genesis};
 use maxminddb::geoip2;
+use protocol::ServerStatus;
+use ratelimit::{RateLimiter, RateLimits};
 use reqwest::header::{CONTENT_TYPE, HeaderName, HeaderValue};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use status::{StatusCache, StatusProtocol, StatusTarget};
 use std::{
     boxed::Box,
+    cmp::Ordering,
+    collections::HashMap,
     error::Error,
+    ffi::OsString,
     fs,
-    io::{Read, Write},
-    net::{IpAddr, TcpStream},
+    io::Write,
+    net::{IpAddr, Ipv4Addr},
     path::PathBuf,
-    sync::Once,
-    time::SystemTime,
+    sync::{Arc, Mutex, MutexGuard, Once, RwLock},
+    time::{Duration, SystemTime},
 };
+use storage::{FileStorage, SqliteStorage, Storage, StorageBackend};
+use tls::TlsConfig;
+use tokio::{io::AsyncReadExt, net::TcpStream, task::JoinSet, time::timeout};
 use toml;
+use toml_edit::{DocumentMut, TableLike};
 use xdg::BaseDirectories;
 
 static START: Once = Once::new();
 
-#[derive(Deserialize)]
+const OSM_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const OSM_READ_TIMEOUT: Duration = Duration::from_secs(5);
+const OSM_MAX_RESPONSE: u64 = 64 * 1024;
+const NICK_CONCURRENCY: usize = 8;
+const NICK_TIMEOUT: Duration = Duration::from_secs(10);
+const NICK_ATTEMPTS: u32 = 3;
+const NICK_BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
     pub cache_time: u32,
-    pub api_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
     pub port: u16,
+    pub bind: Vec<IpAddr>,
+    pub trust_proxy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unix_socket: Option<PathBuf>,
     pub osm_token: String,
     pub propaganda_path: PathBuf,
+    pub storage: StorageBackend,
+    pub key_grace_period: u32,
+    pub status_interval: u32,
+    pub nickname_ttl: u32,
+    pub servers: Vec<StatusTarget>,
+    pub rate_limits: RateLimits,
+}
+
+impl std::default::Default for Config {
+    fn default() -> Config {
+        let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
+        Config {
+            cache_time: 12,
+            api_key: None,
+            port: 3568,
+            bind: vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)],
+            trust_proxy: false,
+            tls: None,
+            unix_socket: None,
+            osm_token: String::new(),
+            propaganda_path: xdg_dirs
+                .get_data_home()
+                .unwrap_or_default()
+                .join("propaganda"),
+            storage: StorageBackend::default(),
+            key_grace_period: 24,
+            status_interval: 30,
+            nickname_ttl: 24,
+            servers: vec![StatusTarget {
+                name: String::from("os-mc"),
+                address: String::from("os-mc.net:8283"),
+                protocol: StatusProtocol::Osmc,
+            }],
+            rate_limits: RateLimits::default(),
+        }
+    }
+}
+
+fn merge_missing(table: &mut dyn TableLike, defaults: &dyn TableLike, prefix: &str) -> Vec<String> {
+    let mut added = Vec::new();
+    for (key, default) in defaults.iter() {
+        match (table.get_mut(key), default.as_table_like()) {
+            (Some(existing), Some(default)) if existing.is_table_like() => {
+                if let Some(existing) = existing.as_table_like_mut() {
+                    added.extend(merge_missing(
+                        existing,
+                        default,
+                        &format!("{}{}.", prefix, key),
+                    ));
+                }
+            }
+            (Some(_), _) => {}
+            (None, _) => {
+                added.push(format!("{}{}", prefix, key));
+                table.insert(key, default.clone());
+            }
+        }
+    }
+    added
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum OverrideKind {
+    String,
+    Integer,
+    Boolean,
+    List,
+}
+
+const OVERRIDABLE_FIELDS: [(&str, OverrideKind); 12] = [
+    ("cache_time", OverrideKind::Integer),
+    ("api_key", OverrideKind::String),
+    ("port", OverrideKind::Integer),
+    ("bind", OverrideKind::List),
+    ("trust_proxy", OverrideKind::Boolean),
+    ("unix_socket", OverrideKind::String),
+    ("osm_token", OverrideKind::String),
+    ("propaganda_path", OverrideKind::String),
+    ("storage", OverrideKind::String),
+    ("key_grace_period", OverrideKind::Integer),
+    ("status_interval", OverrideKind::Integer),
+    ("nickname_ttl", OverrideKind::Integer),
+];
+
+#[derive(Clone, Default)]
+pub struct ConfigOverrides {
+    pub path: Option<PathBuf>,
+    pub values: Vec<(String, String)>,
+}
+
+impl ConfigOverrides {
+    pub fn from_sources(
+        env: impl IntoIterator<Item = (OsString, OsString)>,
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<ConfigOverrides, Box<dyn Error>> {
+        let mut overrides = ConfigOverrides::default();
+        for (name, value) in env {
+            let name = name.to_string_lossy();
+            let Some(field) = name.strip_prefix("NAMEFUL_") else {
+                continue;
+            };
+            let field = field.to_lowercase();
+            if field == "config" {
+                overrides.path = Some(PathBuf::from(value));
+                continue;
+            }
+            if !OVERRIDABLE_FIELDS.iter().any(|(f, _)| *f == field) {
+                return Err(format!("unknown environment variable {}", name).into());
+            }
+            let value = value
+                .into_string()
+                .map_err(|_| format!("{} is not valid unicode", name))?;
+            overrides.values.push((field, value));
+        }
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                return Err(format!("unexpected argument {}", arg).into());
+            };
+            let (flag, value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), value.to_string()),
+                None => (
+                    flag.to_string(),
+                    args.next().ok_or(format!("missing value for --{}", flag))?,
+                ),
+            };
+            let field = flag.replace('-', "_");
+            if field == "config" {
+                overrides.path = Some(PathBuf::from(value));
+            } else if OVERRIDABLE_FIELDS.iter().any(|(f, _)| *f == field) {
+                overrides.values.push((field, value));
+            } else {
+                return Err(format!("unknown flag --{}", flag).into());
+            }
+        }
+        Ok(overrides)
+    }
+
+    fn apply(&self, table: &mut toml::Table) -> Result<(), Box<dyn Error>> {
+        for (field, value) in &self.values {
+            let kind = OVERRIDABLE_FIELDS
+                .iter()
+                .find(|(f, _)| f == field)
+                .map_or(OverrideKind::String, |(_, kind)| *kind);
+            let value = match kind {
+                OverrideKind::String => toml::Value::String(value.clone()),
+                OverrideKind::Integer => toml::Value::Integer(
+                    value
+                        .parse()
+                        .map_err(|e| format!("invalid value for {}: {}", field, e))?,
+                ),
+                OverrideKind::Boolean => toml::Value::Boolean(
+                    value
+                        .parse()
+                        .map_err(|e| format!("invalid value for {}: {}", field, e))?,
+                ),
+                OverrideKind::List => toml::Value::Array(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(|item| toml::Value::String(item.to_string()))
+                        .collect(),
+                ),
+            };
+            table.insert(field.clone(), value);
+        }
+        Ok(())
+    }
 }
 
 impl Config {
-    pub async fn init() -> Result<(), Box<dyn Error>> {
+    pub async fn init(overrides: &ConfigOverrides) -> Result<(), Box<dyn Error>> {
         let error_base64 = "iVBORw0KGgoAAAANSUhEUgAAAEAAAABACAMAAACdt4HsAAAAdVBMVEUAAAD///+qclmbY0mQWT+PXj6BUzl2SzM/KhUzJBErHg0kGAgmGgo6MYlBNZtGOqUFiIgElZUApKQAr68KvLw3Nzc/Pz9KSkpVVVUAzMyUYD5qQDB3QjVJJRBCHQooKCgAf38AaGg0JRIDenqzeV63g2tSPYnw8BGEAAAAAXRSTlMAQObYZgAAAo5JREFUSA3t1oWOM1cAQ+HPNymlzMx9/ycqV/Qz0eJ1GTKFya642iOy6MjXgwHYaVTOACcOYwugTRsEdSgDQMNgBjIvLkiqKcAlBDStQrnoBpVQYw7MXGKD0qSjKrlwg6CJ9B1cqwPJTiVVRUJaoZE2j1eP0CTaIaSdlaSYxLQmaKuzevL+hx8cn76bzppKlKwfIa1Rx/3M1+QZmZJWGvpk9QiTHB+nHV/ni/bnzGRExRrbInUUX77ry+RIUxrSyOoGIzk9Gpu3s3k/+WCzeXszjk6TAbFOnp465sgbN99peq3NHDMGT+Nk9bEOvIhn7XwHePdaon37IeAB4HV6r8x/uhN3HgOQEP9IJf8oeGxnlRSt1QYVrSVFut8gL2nCTEPMLUbvRl/JxKiSoMSLeIBbgK0CEIaKvHzPy6Ei0kAJoioAg0AA+vrrrbz6UvTnPFGmSnSCqOUGoPI6r99OJPpzvkORFwH5Ld/+q6AVaSaFNlBAgx9I04AurkJISyq3XneLBn7OCaFFtUnVQiAQCW6RqIZbIFSqyHKDIfYIKY0mBBRQEC2AbQUlBYQZUVJBtaSg9tjuPFawAyeBtkkJ1bQKNHRPMLMjRZvoeVPifDP0XIxUzweQghTAVhtUKUVEx2SKdHYzh7RRSdMQV/yfiAN5A2iRcNMVV6zcSC/iNY/dALwW7pBzLNmy5J8/96OtgwXLz710wuUbtKPNQRu8pInSCH0ND5QAt+wz/AOVUIgEoWg5TIAAMCtRlcM2yH6VvLyQJwC6FNAwBSb9AZqmIGfrDRqRIpVKSxV0fYMgARkjEJRILjCigIAC7fqIFRoUUCCFdcHOY1rswCMU6EGC5f8CSAHpqsDyfyENoqJiwY8icHkmoi9YwQAAAABJRU5ErkJggg==";
         let error = &BASE64_STANDARD.decode(error_base64)?;
-        let options = StringGenerator {
-            length: 24,
-            prefix: String::from("nmfl"),
-            ..StringGenerator::default()
-        };
-        let ApiKeyResults::String(key) = api_key::string(options) else {
-            return Err("could not generate api key".into());
-        };
         let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
-        let config_path = xdg_dirs.place_config_file("config.toml")?;
+        let config_path = match &overrides.path {
+            Some(path) => path.clone(),
+            None => xdg_dirs.place_config_file("config.toml")?,
+        };
         let maxmind_db_path = xdg_dirs.place_data_file("GeoLite2-City.mmdb")?;
         let fallback_path = xdg_dirs.place_cache_file("skins/.fallback.png")?;
-        if xdg_dirs.find_config_file(&config_path) == None {
+        if !config_path.exists() {
+            xdg_dirs.create_data_directory("propaganda")?;
             let mut config_file = fs::File::create(&config_path)?;
-            write!(
-                &mut config_file,
-                "cache_time = 12\napi_key = \"{}\"\nport = 3568\nosm_token = \"\"\npropaganda_path = \"path/to/propaganda\"",
-                key
-            )?;
+            write!(&mut config_file, "{}", toml::to_string(&Config::default())?)?;
+            println!("Created config at {}", config_path.display());
+        } else {
+            let mut document: DocumentMut = fs::read_to_string(&config_path)?.parse()?;
+            let defaults: DocumentMut = toml::to_string(&Config::default())?.parse()?;
+            let added = merge_missing(document.as_table_mut(), defaults.as_table(), "");
+            if !added.is_empty() {
+                match fs::write(&config_path, document.to_string()) {
+                    Ok(()) => println!("Added {} to config", added.join(", ")),
+                    Err(e) => println!(
+                        "Could not add {} to {}, using defaults: {}",
+                        added.join(", "),
+                        config_path.display(),
+                        e
+                    ),
+                }
+            }
+        }
+        let config = Config::load(overrides)?;
+        let storage = config.storage()?;
+        if let Some(api_key) = &config.api_key {
+            keys::import_key(storage.as_ref(), "config", api_key, vec![Scope::Admin])?;
+            let mut document: DocumentMut = fs::read_to_string(&config_path)?.parse()?;
+            if document.remove("api_key").is_some() {
+                match fs::write(&config_path, document.to_string()) {
+                    Ok(()) => println!("Moved api_key from config into the key store as config"),
+                    Err(e) => println!(
+                        "Imported api_key as config but could not remove it from {}: {}",
+                        config_path.display(),
+                        e
+                    ),
+                }
+            }
+        }
+        if keys::load_keys(storage.as_ref())?.is_empty() {
+            let key = keys::create_key(storage.as_ref(), "admin", vec![Scope::Admin], None, None)?;
+            println!("Generated admin API key: {}", key);
         }
         if xdg_dirs.find_data_file(&maxmind_db_path) == None {
             println!("Downloading MaxMind GeoLite2 DB");
@@ -75,13 +310,240 @@ impl Config {
         }
         Ok(())
     }
-    pub fn new() -> Result<Config, Box<dyn Error>> {
+    pub fn load(overrides: &ConfigOverrides) -> Result<Config, Box<dyn Error>> {
         let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
-        let config_path = xdg_dirs
-            .find_config_file("config.toml")
+        let config_path = overrides
+            .path
+            .clone()
+            .or_else(|| xdg_dirs.find_config_file("config.toml"))
             .ok_or("could not find config toml")?;
         let content = fs::read_to_string(&config_path)?;
-        Ok(toml::from_str(&content)?)
+        let mut table: toml::Table = toml::from_str(&content)?;
+        overrides.apply(&mut table)?;
+        let config: Config = table.try_into()?;
+        config.validate()?;
+        Ok(config)
+    }
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let mut problems = Vec::new();
+        if self.port == 0 {
+            problems.push(String::from("port must not be 0"));
+        }
+        if self.bind.is_empty() && self.unix_socket.is_none() {
+            problems.push(String::from("bind is empty and no unix_socket is set"));
+        }
+        if let Some(tls) = &self.tls {
+            for (name, path) in [("cert", &tls.cert), ("key", &tls.key)] {
+                if !path.is_file() {
+                    problems.push(format!("tls.{} {} is not a file", name, path.display()));
+                }
+            }
+        }
+        if self.cache_time == 0 {
+            problems.push(String::from("cache_time must be at least 1 hour"));
+        }
+        if self.nickname_ttl == 0 {
+            problems.push(String::from("nickname_ttl must be at least 1 hour"));
+        }
+        if self.status_interval == 0 {
+            problems.push(String::from("status_interval must be at least 1 second"));
+        }
+        for (i, target) in self.servers.iter().enumerate() {
+            if target.name.is_empty() || target.name.contains('/') {
+                problems.push(format!(
+                    "servers[{}].name must be non-empty and contain no '/'",
+                    i
+                ));
+            } else if self.servers[..i].iter().any(|t| t.name == target.name) {
+                problems.push(format!(
+                    "servers[{}].name {} is a duplicate",
+                    i, target.name
+                ));
+            }
+            if target.address.is_empty() {
+                problems.push(format!("servers[{}].address is empty", i));
+            }
+        }
+        if self.osm_token.is_empty() {
+            problems.push(String::from("osm_token is empty"));
+        }
+        if !self.propaganda_path.is_dir() {
+            problems.push(format!(
+                "propaganda_path {} is not a directory",
+                self.propaganda_path.display()
+            ));
+        }
+        for (route, limit) in self.rate_limits.iter() {
+            if limit.capacity == 0 {
+                problems.push(format!("rate_limits.{}.capacity must not be 0", route));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("invalid config:\n  - {}", problems.join("\n  - ")).into())
+        }
+    }
+    pub fn restart_required(&self, other: &Config) -> Vec<&'static str> {
+        [
+            ("cache_time", self.cache_time != other.cache_time),
+            ("port", self.port != other.port),
+            ("bind", self.bind != other.bind),
+            ("trust_proxy", self.trust_proxy != other.trust_proxy),
+            ("tls", self.tls != other.tls),
+            ("unix_socket", self.unix_socket != other.unix_socket),
+            ("storage", self.storage != other.storage),
+            (
+                "status_interval",
+                self.status_interval != other.status_interval,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(field, changed)| changed.then_some(field))
+        .collect()
+    }
+    pub fn storage(&self) -> Result<Box<dyn Storage>, Box<dyn Error>> {
+        match self.storage {
+            StorageBackend::File => Ok(Box::new(FileStorage)),
+            StorageBackend::Sqlite => Ok(Box::new(SqliteStorage::open()?)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    config: Arc<RwLock<Arc<Config>>>,
+    overrides: Arc<ConfigOverrides>,
+    storage: Arc<dyn Storage>,
+    status: Arc<StatusCache>,
+    auth_failures: Arc<AuthFailures>,
+    rate_limiter: Arc<RateLimiter>,
+    data_lock: Arc<Mutex<()>>,
+}
+
+impl AppState {
+    pub fn new(config: Config, overrides: ConfigOverrides) -> Result<AppState, Box<dyn Error>> {
+        Ok(AppState {
+            storage: Arc::from(config.storage()?),
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            overrides: Arc::new(overrides),
+            status: Arc::new(StatusCache::default()),
+            auth_failures: Arc::new(AuthFailures::default()),
+            rate_limiter: Arc::new(RateLimiter::default()),
+            data_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        match self.config.read() {
+            Ok(config) => config.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn lock_data(&self) -> MutexGuard<'_, ()> {
+        match self.data_lock.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    pub fn storage(&self) -> Arc<dyn Storage> {
+        self.storage.clone()
+    }
+
+    pub fn status(&self) -> &StatusCache {
+        &self.status
+    }
+
+    pub fn auth_failures(&self) -> &AuthFailures {
+        &self.auth_failures
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    pub async fn poll_status(&self) -> Result<(), Box<dyn Error>> {
+        let config = self.config();
+        self.status.retain(&config.servers);
+        let mut polls = JoinSet::new();
+        for target in config.servers.clone() {
+            let status = self.status.clone();
+            polls.spawn(async move {
+                status
+                    .poll(&target)
+                    .await
+                    .map_err(|e| format!("{}: {}", target.name, e))
+            });
+        }
+        let mut errors = Vec::new();
+        while let Some(result) = polls.join_next().await {
+            if let Err(e) = result? {
+                errors.push(e);
+            }
+        }
+        let (up, down): (Vec<_>, Vec<_>) = config
+            .servers
+            .iter()
+            .filter_map(|target| self.status.get(&target.name).map(|c| (target, c)))
+            .partition(|(_, cached)| cached.last_error.is_none());
+        // Session tracking only sees the sampled player names, so the peak is
+        // based on the reported online count instead.
+        let count = up
+            .iter()
+            .map(|(_, cached)| {
+                cached
+                    .status
+                    .online
+                    .map_or(cached.status.players.len(), |online| online as usize)
+            })
+            .sum();
+        // A partial sample cannot tell who left, so those servers are left out
+        // of session tracking and their open sessions end now.
+        let online: Vec<(String, String)> = up
+            .into_iter()
+            .filter(|(_, cached)| !cached.is_partial())
+            .flat_map(|(target, cached)| {
+                cached
+                    .status
+                    .players
+                    .into_iter()
+                    .map(|player| (target.name.clone(), player))
+            })
+            .collect();
+        let down: Vec<(String, i64)> = down
+            .into_iter()
+            .map(|(target, cached)| (target.name.clone(), cached.last_updated))
+            .collect();
+        sessions::record_players(
+            self.storage.as_ref(),
+            &online,
+            count,
+            &down,
+            config.status_interval,
+        )?;
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", ").into())
+        }
+    }
+
+    pub fn reload(&self) -> Result<(), Box<dyn Error>> {
+        let config = Arc::new(Config::load(&self.overrides)?);
+        let restart = self.config().restart_required(&config);
+        if !restart.is_empty() {
+            println!(
+                "Changes to {} take effect after a restart",
+                restart.join(", ")
+            );
+        }
+        match self.config.write() {
+            Ok(mut current) => *current = config,
+            Err(poisoned) => *poisoned.into_inner() = config,
+        }
+        Ok(())
     }
 }
 
@@ -246,29 +708,289 @@ pub fn backup(json: &mut Value) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn fetch_osm_info() -> Result<Value, Box<dyn Error>> {
-    let mut message_length: usize = 0;
-    let mut header_read = false;
-    let mut stream = TcpStream::connect("os-mc.net:8283")?;
-    let mut buffer = Vec::new();
-    let header: [u8; 4];
-    stream.read_to_end(&mut buffer)?;
+pub fn etag(json: &Value) -> String {
+    format!("\"{:x}\"", Sha256::digest(json.to_string().as_bytes()))
+}
+
+pub fn etag_matches(if_match: &str, json: &Value) -> bool {
+    let tag = etag(json);
+    if_match
+        .split(',')
+        .map(|t| t.trim())
+        .any(|t| t == "*" || t == tag)
+}
+
+fn compare_json(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        _ => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+pub fn is_query_param(name: &str) -> bool {
+    ["offset", "limit", "sort", "fields"].contains(&name)
+        || name.starts_with("filter[") && name.ends_with(']')
+}
+
+pub fn query_array(
+    array: &[Value],
+    params: &HashMap<String, String>,
+) -> Result<Value, Box<dyn Error>> {
+    let offset = match params.get("offset") {
+        Some(o) => o.parse::<usize>()?,
+        None => 0,
+    };
+    let limit = match params.get("limit") {
+        Some(l) => Some(l.parse::<usize>()?),
+        None => None,
+    };
+    let fields: Option<Vec<&str>> = params.get("fields").map(|f| f.split(',').collect());
+    let filters: Vec<(&str, &String)> = params
+        .iter()
+        .filter_map(|(k, v)| Some((k.strip_prefix("filter[")?.strip_suffix(']')?, v)))
+        .collect();
+
+    let mut items: Vec<&Value> = array
+        .iter()
+        .filter(|item| {
+            filters.iter().all(|(k, v)| match &item[*k] {
+                Value::String(s) => s == *v,
+                Value::Null => false,
+                other => serde_json::from_str::<Value>(v).is_ok_and(|v| &v == other),
+            })
+        })
+        .collect();
+    if let Some(sort) = params.get("sort") {
+        let (field, descending) = match sort.strip_prefix('-') {
+            Some(field) => (field, true),
+            None => (sort.as_str(), false),
+        };
+        items.sort_by(|a, b| {
+            let ordering = compare_json(&a[field], &b[field]);
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+
+    let total = items.len();
+    let page: Vec<Value> = items
+        .into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .map(|item| match (&fields, item.as_object()) {
+            (Some(fields), Some(object)) => Value::Object(
+                object
+                    .iter()
+                    .filter(|(k, _)| fields.contains(&k.as_str()))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            ),
+            _ => item.clone(),
+        })
+        .collect();
+    Ok(json!({"total":total,"offset":offset,"limit":limit,"items":page}))
+}
+
+pub fn generate_key() -> Result<String, Box<dyn Error>> {
+    let options = StringGenerator {
+        length: 24,
+        prefix: String::from("nmfl"),
+        ..StringGenerator::default()
+    };
+    let ApiKeyResults::String(key) = api_key::string(options) else {
+        return Err("could not generate api key".into());
+    };
+    Ok(key)
+}
+
+pub fn pointer_starts_with(pointer: &str, prefix: &str) -> bool {
+    match pointer.strip_prefix(prefix) {
+        Some(rest) => prefix.is_empty() || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+pub fn audit(
+    ip: IpAddr,
+    key_id: &str,
+    method: &str,
+    pointer: &str,
+    before: Value,
+    after: Value,
+) -> Result<(), Box<dyn Error>> {
+    let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
+    let log_path = xdg_dirs.place_data_file("audit.jsonl")?;
+    let now: DateTime<Utc> = SystemTime::now().into();
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)?;
+    writeln!(
+        &mut log,
+        "{}",
+        json!({
+            "timestamp": now.timestamp(),
+            "ip": ip,
+            "key_id": key_id,
+            "method": method,
+            "pointer": pointer,
+            "before": before,
+            "after": after,
+        })
+    )?;
+    Ok(())
+}
+
+pub fn audit_history(
+    path: Option<&str>,
+    since: Option<i64>,
+    until: Option<i64>,
+) -> Result<Value, Box<dyn Error>> {
+    let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
+    let Some(log_path) = xdg_dirs.find_data_file("audit.jsonl") else {
+        return Ok(json!([]));
+    };
+    let mut entries = Vec::new();
+    for line in fs::read_to_string(&log_path)?.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let Ok(entry) = serde_json::from_str::<Value>(line) else {
+            eprintln!("Skipping malformed audit entry: {}", line);
+            continue;
+        };
+        let (Some(timestamp), Some(pointer)) =
+            (entry["timestamp"].as_i64(), entry["pointer"].as_str())
+        else {
+            eprintln!(
+                "Skipping audit entry without timestamp or pointer: {}",
+                line
+            );
+            continue;
+        };
+        if path.is_some_and(|p| !pointer_starts_with(pointer, p))
+            || since.is_some_and(|s| timestamp < s)
+            || until.is_some_and(|u| timestamp > u)
+        {
+            continue;
+        }
+        entries.push(entry);
+    }
+    Ok(json!(entries))
+}
 
-    if buffer.len() >= 4 {
-        header_read = true;
-        header = buffer[0..4].try_into()?;
-        message_length = u32::from_be_bytes(header).try_into()?;
+pub fn split_namespace(key_path: &str) -> (&str, &str) {
+    let key_path = key_path.strip_prefix('/').unwrap_or(key_path);
+    match key_path.find('/') {
+        Some(i) => (&key_path[..i], &key_path[i..]),
+        None => (key_path, ""),
     }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VirtualDocument {
+    Nicked,
+    Online,
+    Backups,
+}
+
+impl VirtualDocument {
+    pub const ALL: [VirtualDocument; 3] = [
+        VirtualDocument::Nicked,
+        VirtualDocument::Online,
+        VirtualDocument::Backups,
+    ];
 
-    if header_read && message_length != 0 && buffer.len() >= message_length * 2 + 8 {
-        let data_str = String::from_utf8(buffer[4..4 + message_length * 2].to_vec())?;
-        let data_json: Value = serde_json::from_str(&data_str.replace(" ", ""))?;
-        Ok(data_json)
-    } else {
-        Err("invalid response".into())
+    pub fn name(&self) -> &'static str {
+        match self {
+            VirtualDocument::Nicked => "nicked",
+            VirtualDocument::Online => "online",
+            VirtualDocument::Backups => "backups",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<VirtualDocument> {
+        VirtualDocument::ALL
+            .into_iter()
+            .find(|document| document.name() == name)
+    }
+
+    pub async fn resolve(
+        &self,
+        state: &AppState,
+        storage: &dyn Storage,
+        pointer: &str,
+    ) -> Result<Option<Value>, Box<dyn Error>> {
+        match self {
+            VirtualDocument::Nicked => Ok(storage
+                .load("nick-cache")?
+                .ok_or("could not find nick cache")?
+                .pointer(pointer)
+                .cloned()),
+            VirtualDocument::Online => {
+                let statuses: serde_json::Map<String, Value> = state
+                    .config()
+                    .servers
+                    .iter()
+                    .filter_map(|t| {
+                        Some((t.name.clone(), json!(state.status().get(&t.name)?.status)))
+                    })
+                    .collect();
+                Ok(Value::Object(statuses).pointer(pointer).cloned())
+            }
+            VirtualDocument::Backups => {
+                let (timestamp, pointer) = split_namespace(pointer);
+                if timestamp.is_empty() {
+                    return Ok(Some(json!(storage.backups()?)));
+                }
+                let Ok(timestamp) = timestamp.parse::<i64>() else {
+                    return Ok(None);
+                };
+                let Some(backup) = storage.load_backup(timestamp)? else {
+                    return Ok(None);
+                };
+                Ok(backup.pointer(pointer).cloned())
+            }
+        }
     }
 }
 
+pub async fn fetch_osm_info(address: &str) -> Result<ServerStatus, Box<dyn Error>> {
+    fetch_osm_info_within(address, OSM_READ_TIMEOUT).await
+}
+
+async fn fetch_osm_info_within(
+    address: &str,
+    read_timeout: Duration,
+) -> Result<ServerStatus, Box<dyn Error>> {
+    let stream = timeout(OSM_CONNECT_TIMEOUT, TcpStream::connect(address))
+        .await
+        .map_err(|_| format!("timed out connecting to {}", address))??;
+    let mut buffer = Vec::new();
+    timeout(
+        read_timeout,
+        stream.take(OSM_MAX_RESPONSE + 1).read_to_end(&mut buffer),
+    )
+    .await
+    .map_err(|_| format!("timed out reading from {}", address))??;
+    if buffer.len() as u64 > OSM_MAX_RESPONSE {
+        return Err(format!(
+            "response from {} exceeds {} bytes",
+            address, OSM_MAX_RESPONSE
+        )
+        .into());
+    }
+    protocol::decode_frame(&buffer)
+        .map_err(|e| format!("invalid response from {}: {}", address, e).into())
+}
+
 pub async fn download_skin(username: &str) -> Result<String, Box<dyn Error>> {
     let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
     let skin_path = xdg_dirs.place_cache_file(format!("skins/{}.png", username))?;
@@ -330,8 +1052,19 @@ pub fn dir_to_json(path: PathBuf) -> Result<Value, Box<dyn Error>> {
     Ok(json!(result))
 }
 
-pub async fn get_nickname(config: &Config, username: &str) -> Result<Value, Box<dyn Error>> {
-    let client = reqwest::Client::new();
+/// `None` when the player has no nickname set.
+pub async fn get_nickname(
+    config: &Config,
+    username: &str,
+) -> Result<Option<Value>, Box<dyn Error>> {
+    fetch_nickname(&reqwest::Client::new(), &config.osm_token, username).await
+}
+
+async fn fetch_nickname(
+    client: &reqwest::Client,
+    token: &str,
+    username: &str,
+) -> Result<Option<Value>, Box<dyn Error>> {
     let resp = client
         .get(format!(
             "https://micro.os-mc.net/profile_service/ess/{}",
@@ -341,34 +1074,82 @@ pub async fn get_nickname(config: &Config, username: &str) -> Result<Value, Box<
             HeaderName::from_lowercase(b"content-type")?,
             HeaderValue::from_str("application/json")?,
         )
-        .body(format!("{{\"token\":\"{}\"}}", config.osm_token))
+        .body(format!("{{\"token\":\"{}\"}}", token))
+        .timeout(NICK_TIMEOUT)
         .send()
-        .await?;
+        .await?
+        .error_for_status()?;
     let json_object: Value = serde_json::from_str(&resp.text().await?)?;
-    json_object
-        .get("nickname")
-        .ok_or("could not retrieve nickname".into())
-        .map(|j| j.clone())
-        .and_then(|j| {
-            if j.is_null() {
-                Err("nickname is null".into())
-            } else {
-                Ok(j)
-            }
-        })
+    match json_object.get("nickname") {
+        Some(Value::Null) => Ok(None),
+        Some(nickname) => Ok(Some(nickname.clone())),
+        None => Err("could not retrieve nickname".into()),
+    }
 }
 
-pub async fn cache_nicks() -> Result<(), Box<dyn Error>> {
-    let config = Config::new()?;
-    let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
-    let Some(data) = xdg_dirs.find_data_file("data.json") else {
+fn is_transient(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<reqwest::Error>().is_some_and(|e| {
+        e.is_timeout()
+            || e.is_connect()
+            || e.status().is_some_and(|status| {
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            })
+    })
+}
+
+async fn fetch_nickname_with_retry(
+    client: &reqwest::Client,
+    token: &str,
+    username: &str,
+) -> Result<Option<Value>, String> {
+    let mut attempt = 1;
+    loop {
+        let (transient, message) = match fetch_nickname(client, token, username).await {
+            Ok(nickname) => return Ok(nickname),
+            Err(e) => (is_transient(e.as_ref()), e.to_string()),
+        };
+        if !transient || attempt >= NICK_ATTEMPTS {
+            return Err(message);
+        }
+        tokio::time::sleep(NICK_BACKOFF * 2u32.pow(attempt - 1)).await;
+        attempt += 1;
+    }
+}
+
+async fn lookup_nicknames(
+    config: &Config,
+    usernames: Vec<String>,
+) -> Result<Vec<Result<Option<Value>, String>>, Box<dyn Error>> {
+    let client = reqwest::Client::new();
+    let mut nicknames = vec![Err(String::new()); usernames.len()];
+    let mut lookups = JoinSet::new();
+    for (i, username) in usernames.into_iter().enumerate() {
+        if lookups.len() >= NICK_CONCURRENCY
+            && let Some(result) = lookups.join_next().await
+        {
+            let (i, nickname) = result?;
+            nicknames[i] = nickname;
+        }
+        let client = client.clone();
+        let token = config.osm_token.clone();
+        lookups.spawn(async move {
+            (
+                i,
+                fetch_nickname_with_retry(&client, &token, &username).await,
+            )
+        });
+    }
+    while let Some(result) = lookups.join_next().await {
+        let (i, nickname) = result?;
+        nicknames[i] = nickname;
+    }
+    Ok(nicknames)
+}
+
+pub async fn cache_nicks(config: &Config, storage: &dyn Storage) -> Result<(), Box<dyn Error>> {
+    let Some(json) = storage.load("data")? else {
         return Err("could not find data json".into());
     };
-    let nick = match xdg_dirs.find_data_file("nick-cache.json") {
-        Some(d) => d,
-        None => xdg_dirs.place_data_file("nick-cache.json")?,
-    };
-    let json = read_json_from_file(&data)?;
 
     let leaders: &Vec<Value> = {
         let Some(leaders) = json.pointer("/leadership") else {
@@ -389,36 +1170,66 @@ pub async fn cache_nicks() -> Result<(), Box<dyn Error>> {
         members_array
     };
 
-    let mut leaders_vec = Vec::new();
-    for leader in leaders {
-        let Some(title) = leader["title"].as_str() else {
-            return Err("could not convert value to str slice".into());
-        };
-        let Some(username) = leader["username"].as_str() else {
+    let mut usernames = Vec::new();
+    for entry in leaders.iter().chain(members) {
+        let Some(username) = entry["username"].as_str() else {
             return Err("could not convert value to str slice".into());
         };
-        leaders_vec.push(match get_nickname(&config, username).await {
-            Ok(n) => json!({"title":title,"nickname":n,"username":username}),
-            Err(..) => json!({"title":title,"nickname":username,"username":username}),
-        });
+        usernames.push(username.to_string());
     }
 
-    let mut members_vec = Vec::new();
-    for member in members {
-        let Some(username) = member["username"].as_str() else {
-            return Err("could not convert value to str slice".into());
+    let nicknames = lookup_nicknames(config, usernames.clone()).await?;
+    let cached = nicknames::load_nicknames(storage)?;
+    let mut failed = Vec::new();
+    let mut fetched = Vec::new();
+    let mut entries = Vec::new();
+    for ((entry, username), nickname) in
+        leaders.iter().chain(members).zip(&usernames).zip(nicknames)
+    {
+        let Some(mut entry) = entry.as_object().cloned() else {
+            return Err("could not convert value to object".into());
         };
-        members_vec.push(match get_nickname(&config, username).await {
-            Ok(n) => json!({"username":n}),
-            Err(..) => json!({"username":username}),
-        });
+        let (nickname, status) = match nickname {
+            Ok(n) => {
+                fetched.push((username.clone(), n.clone()));
+                match n {
+                    Some(n) => (n, "fetched"),
+                    None => (json!(username), "none"),
+                }
+            }
+            Err(e) => {
+                failed.push(format!("{} ({})", username, e));
+                match cached.get(&username.to_lowercase()) {
+                    Some(cached) => match &cached.nickname {
+                        Some(n) => (n.clone(), "cached"),
+                        None => (json!(username), "none"),
+                    },
+                    None => (json!(username), "fallback"),
+                }
+            }
+        };
+        entry.insert(String::from("nickname"), nickname);
+        entry.insert(String::from("nickname_status"), json!(status));
+        entries.push(Value::Object(entry));
+    }
+    nicknames::store_nicknames(storage, fetched)?;
+    let members_vec = entries.split_off(leaders.len());
+    let leaders_vec = entries;
+
+    println!(
+        "Cached {} nicknames, {} lookups failed",
+        usernames.len() - failed.len(),
+        failed.len()
+    );
+    if !failed.is_empty() {
+        println!("Failed nickname lookups: {}", failed.join(", "));
     }
 
     let now: DateTime<Utc> = SystemTime::now().try_into()?;
 
-    write_json_to_file(
-        &mut json!({"last_updated":now.to_string(),"leadership":leaders_vec,"member_list":members_vec}),
-        &nick,
+    storage.save(
+        "nick-cache",
+        &json!({"last_updated":now.to_string(),"leadership":leaders_vec,"member_list":members_vec}),
     )?;
     Ok(())
 }
@@ -431,3 +1242,234 @@ pub fn get_geoip_data(ip: IpAddr, db: PathBuf) -> Result<Value, Box<dyn Error>>
     };
     Ok(json!(city))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(env: &[(&str, &str)], args: &[&str]) -> Result<ConfigOverrides, Box<dyn Error>> {
+        ConfigOverrides::from_sources(
+            env.iter()
+                .map(|(n, v)| (OsString::from(n), OsString::from(v))),
+            args.iter().map(|a| a.to_string()),
+        )
+    }
+
+    #[test]
+    fn overrides_apply_file_then_env_then_cli() {
+        let mut table: toml::Table =
+            toml::from_str("port = 1\ncache_time = 2\nosm_token = \"file\"").unwrap();
+        overrides(
+            &[("NAMEFUL_PORT", "10"), ("NAMEFUL_OSM_TOKEN", "env")],
+            &["--port", "100"],
+        )
+        .unwrap()
+        .apply(&mut table)
+        .unwrap();
+        assert_eq!(table["port"].as_integer(), Some(100));
+        assert_eq!(table["cache_time"].as_integer(), Some(2));
+        assert_eq!(table["osm_token"].as_str(), Some("env"));
+    }
+
+    #[test]
+    fn api_key_and_bind_are_overridable() {
+        let mut table = toml::Table::new();
+        overrides(
+            &[
+                ("NAMEFUL_API_KEY", "secret"),
+                ("NAMEFUL_BIND", "0.0.0.0, ::"),
+            ],
+            &[],
+        )
+        .unwrap()
+        .apply(&mut table)
+        .unwrap();
+        let config: Config = table.try_into().unwrap();
+        assert_eq!(config.api_key.as_deref(), Some("secret"));
+        assert_eq!(
+            config.bind,
+            [
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED)
+            ]
+        );
+        let cli = overrides(&[], &["--api-key", "secret", "--bind=127.0.0.1"]).unwrap();
+        assert_eq!(cli.values.len(), 2);
+    }
+
+    #[test]
+    fn cli_flags_accept_both_value_forms() {
+        let spaced = overrides(&[], &["--key-grace-period", "5", "--osm-token", "a=b"]).unwrap();
+        let joined = overrides(&[], &["--key-grace-period=5", "--osm-token=a=b"]).unwrap();
+        for o in [spaced, joined] {
+            assert_eq!(
+                o.values,
+                [
+                    (String::from("key_grace_period"), String::from("5")),
+                    (String::from("osm_token"), String::from("a=b")),
+                ]
+            );
+        }
+        assert!(overrides(&[], &["--port"]).is_err());
+        assert!(overrides(&[], &["port=1"]).is_err());
+    }
+
+    #[test]
+    fn config_path_comes_from_env_or_cli() {
+        let env = overrides(&[("NAMEFUL_CONFIG", "/env.toml")], &[]).unwrap();
+        assert_eq!(env.path, Some(PathBuf::from("/env.toml")));
+        let cli = overrides(&[("NAMEFUL_CONFIG", "/env.toml")], &["--config=/cli.toml"]).unwrap();
+        assert_eq!(cli.path, Some(PathBuf::from("/cli.toml")));
+        assert!(cli.values.is_empty());
+    }
+
+    #[test]
+    fn unknown_overrides_are_rejected() {
+        assert!(overrides(&[("NAMEFUL_PROT", "1")], &[]).is_err());
+        assert!(overrides(&[], &["--prot", "1"]).is_err());
+        assert!(overrides(&[("HOME", "/root")], &[]).is_ok());
+    }
+
+    #[test]
+    fn non_unicode_env_values_are_reported() {
+        use std::os::unix::ffi::OsStringExt;
+        let bad = OsString::from_vec(vec![0xff, 0xfe]);
+        let env = [
+            (OsString::from("OTHER"), bad.clone()),
+            (OsString::from_vec(vec![b'X', 0xff]), OsString::from("1")),
+        ];
+        assert!(ConfigOverrides::from_sources(env, Vec::new()).is_ok());
+        let env = [(OsString::from("NAMEFUL_OSM_TOKEN"), bad)];
+        assert!(ConfigOverrides::from_sources(env, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn merge_missing_keeps_comments_and_order() {
+        let original = "# nameful config\nport = 1 # custom port\n\n[rate_limits.render]\ncapacity = 5\nper_minute = 5\n";
+        let mut document: DocumentMut = original.parse().unwrap();
+        let defaults: DocumentMut = toml::to_string(&Config::default())
+            .unwrap()
+            .parse()
+            .unwrap();
+        let added = merge_missing(document.as_table_mut(), defaults.as_table(), "");
+        assert!(added.contains(&String::from("cache_time")));
+        assert!(added.contains(&String::from("rate_limits.online")));
+        assert!(
+            !added
+                .iter()
+                .any(|a| a == "port" || a.starts_with("rate_limits.render"))
+        );
+        let merged = document.to_string();
+        assert!(merged.starts_with("# nameful config\nport = 1 # custom port\n"));
+        assert!(merged.contains("[rate_limits.render]\ncapacity = 5\nper_minute = 5\n"));
+        let config: Config = toml::from_str(&merged).unwrap();
+        assert_eq!(config.port, 1);
+        assert_eq!(config.rate_limits.render.capacity, 5);
+    }
+
+    async fn serve_once(response: Vec<u8>, hold: Duration) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = tokio::io::AsyncWriteExt::write_all(&mut stream, &response).await;
+            tokio::time::sleep(hold).await;
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn fetch_osm_info_decodes_a_frame() {
+        let status = ServerStatus {
+            players: vec![String::from("Steve")],
+            ..ServerStatus::default()
+        };
+        let address = serve_once(protocol::encode_frame(&status).unwrap(), Duration::ZERO).await;
+        let fetched = fetch_osm_info(&address).await.unwrap();
+        assert_eq!(fetched.players, ["Steve"]);
+    }
+
+    #[tokio::test]
+    async fn fetch_osm_info_times_out_on_a_silent_server() {
+        let address = serve_once(Vec::new(), Duration::from_secs(1)).await;
+        let error = fetch_osm_info_within(&address, Duration::from_millis(50))
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("timed out reading"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn fetch_osm_info_rejects_oversized_responses() {
+        let response = vec![0; OSM_MAX_RESPONSE as usize + 16];
+        let address = serve_once(response, Duration::ZERO).await;
+        let error = fetch_osm_info(&address).await.unwrap_err().to_string();
+        assert!(error.contains("exceeds"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn fetch_osm_info_rejects_truncated_and_garbage_responses() {
+        let mut truncated = 100u32.to_be_bytes().to_vec();
+        truncated.extend_from_slice(&[0, b'{']);
+        let garbage = b"HTTP/1.1 400 Bad Request\r\n\r\n".to_vec();
+        for response in [truncated, garbage, vec![0, 0]] {
+            let address = serve_once(response, Duration::ZERO).await;
+            let error = fetch_osm_info(&address).await.unwrap_err().to_string();
+            assert!(error.contains("invalid response"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn if_match_uses_strong_comparison() {
+        let json = json!({"a": 1});
+        let tag = etag(&json);
+        assert!(etag_matches(&tag, &json));
+        assert!(etag_matches(&format!("\"other\", {}", tag), &json));
+        assert!(etag_matches("*", &json));
+        assert!(!etag_matches(&format!("W/{}", tag), &json));
+        assert!(!etag_matches(&etag(&json!({"a": 2})), &json));
+    }
+
+    #[test]
+    fn reload_reports_fields_that_need_a_restart() {
+        let current = Config::default();
+        let mut reloaded = Config {
+            osm_token: String::from("token"),
+            nickname_ttl: 1,
+            ..Config::default()
+        };
+        assert!(current.restart_required(&reloaded).is_empty());
+        reloaded.port = 1;
+        reloaded.status_interval = 5;
+        reloaded.tls = Some(TlsConfig {
+            cert: PathBuf::from("cert.pem"),
+            key: PathBuf::from("key.pem"),
+        });
+        assert_eq!(
+            current.restart_required(&reloaded),
+            ["port", "tls", "status_interval"]
+        );
+    }
+
+    #[test]
+    fn split_namespace_takes_first_segment() {
+        assert_eq!(split_namespace("/nicked"), ("nicked", ""));
+        assert_eq!(
+            split_namespace("/nicked/leadership/0"),
+            ("nicked", "/leadership/0")
+        );
+        assert_eq!(split_namespace("/nicke"), ("nicke", ""));
+        assert_eq!(split_namespace(""), ("", ""));
+    }
+
+    #[test]
+    fn only_registered_namespaces_are_virtual() {
+        assert!(VirtualDocument::from_name("nicked") == Some(VirtualDocument::Nicked));
+        assert!(VirtualDocument::from_name("online") == Some(VirtualDocument::Online));
+        assert!(VirtualDocument::from_name("backups") == Some(VirtualDocument::Backups));
+        for name in ["nicke", "nickedx", "Nicked", "member_list", ""] {
+            assert!(VirtualDocument::from_name(name).is_none(), "{}", name);
+        }
+        assert!(VirtualDocument::from_name(split_namespace("/nicke/x").0).is_none());
+    }
+}
@@ -1,42 +1,96 @@
 use axum::{
-    Router,
+    RequestExt, Router,
     body::Body,
-    extract::{Path, Request},
-    http::{StatusCode, header},
+    extract::{Extension, Path, Query, Request, State},
+    http::{HeaderMap, StatusCode, header},
     middleware::{self, Next},
     response::{IntoResponse, Json, Response},
-    routing::{delete, get, post, put},
+    routing::{delete, get, patch, post, put},
 };
-use axum_client_ip::XRealIp;
+use axum_client_ip::{ClientIp, ClientIpSource};
 use clokwerk::{AsyncScheduler, TimeUnits};
 use json_value_remove::Remove;
-use nameful_api::*;
+use nameful_api::{
+    keys::{self, ApiKey, PathRule, Scope},
+    status::CachedStatus,
+    storage::Storage,
+    tls::{self, TlsListener, TlsPeer},
+    *,
+};
 use rand::random_range;
 use serde_json::{Value, json};
+use socket2::{Domain, Protocol, Socket, Type};
 use std::{
+    collections::HashMap,
+    error::Error,
+    fs, io,
     net::{IpAddr, SocketAddr},
+    os::unix::fs::FileTypeExt,
+    sync::Arc,
     time::Duration,
 };
+use tokio::{
+    net::{TcpListener, UnixListener},
+    signal::unix::{SignalKind, signal},
+    task::JoinSet,
+};
 use tokio_util::io::ReaderStream;
 use xdg::BaseDirectories;
 
 #[tokio::main]
 async fn main() {
-    if let Err(e) = Config::init().await {
+    let overrides =
+        match ConfigOverrides::from_sources(std::env::vars_os(), std::env::args().skip(1)) {
+            Ok(o) => o,
+            Err(e) => {
+                eprintln!("API Crashed due to: {e}");
+                return;
+            }
+        };
+    if let Err(e) = Config::init(&overrides).await {
         eprintln!("API Crashed due to: {e}");
         return;
     }
-    let config = match Config::new() {
+    let config = match Config::load(&overrides) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("API Crashed due to: {e}");
             return;
         }
     };
+    let state = match AppState::new(config, overrides) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("API Crashed due to: {e}");
+            return;
+        }
+    };
+    let config = state.config();
     let mut scheduler = AsyncScheduler::new();
-    scheduler.every(config.cache_time.hours()).run(async || {
-        if let Err(e) = cache_nicks().await {
-            println!("Caching Error: {}", e)
+    let cache_state = state.clone();
+    scheduler.every(config.cache_time.hours()).run(move || {
+        let state = cache_state.clone();
+        async move {
+            if let Err(e) = cache_nicks(&state.config(), state.storage().as_ref()).await {
+                println!("Caching Error: {}", e)
+            }
+        }
+    });
+    let status_state = state.clone();
+    scheduler
+        .every(config.status_interval.seconds())
+        .run(move || {
+            let state = status_state.clone();
+            async move {
+                if let Err(e) = state.poll_status().await {
+                    println!("Status Error: {}", e)
+                }
+            }
+        });
+    let initial_state = state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = initial_state.poll_status().await {
+            println!("Status Error: {}", e)
         }
     });
     tokio::spawn(async move {
@@ -45,6 +99,22 @@ async fn main() {
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
     });
+    let reload_state = state.clone();
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Could not listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            match reload_state.reload() {
+                Ok(()) => println!("Config reloaded"),
+                Err(e) => eprintln!("Config reload failed, keeping current config: {}", e),
+            }
+        }
+    });
     let get_routes = Router::new()
         .route(
             "/",
@@ -54,137 +124,350 @@ async fn main() {
         .route("/data{*key_path}", get(data_path))
         .route("/splash", get(splash))
         .route("/propaganda", get(propaganda))
-        .route("/online", get(online))
         .route("/ip", get(ip))
-        .route("/geoip", get(geoip))
+        .route("/geoip", get(geoip));
+    let online_routes = Router::new()
+        .route("/online", get(online))
+        .route("/online/{username}", get(player_online))
+        .route("/servers/{server}/online", get(server_online))
+        .route("/status", get(status))
+        .route("/status/{server}", get(server_status))
+        .route("/players/peaks", get(player_peaks))
+        .route("/players/{username}/sessions", get(player_sessions))
+        .route("/players/{username}/playtime", get(player_playtime))
+        .route_layer(middleware::from_fn_with_state(
+            (state.clone(), "online"),
+            rate_limit,
+        ));
+    let geoip_routes = Router::new()
         .route("/geoip/{ip}", get(geoip_with_ip))
+        .route_layer(middleware::from_fn_with_state(
+            (state.clone(), "geoip"),
+            rate_limit,
+        ));
+    let nickname_routes = Router::new()
         .route("/nickname/{username}", get(nickname))
+        .route_layer(middleware::from_fn_with_state(
+            (state.clone(), "nickname"),
+            rate_limit,
+        ));
+    let render_routes = Router::new()
         .route(
             "/render/{armored}/{render_type}/{username}/{width}",
             get(render),
-        );
+        )
+        .route_layer(middleware::from_fn_with_state(
+            (state.clone(), "render"),
+            rate_limit,
+        ));
     let put_routes = Router::new()
         .route("/data{*key_path}", put(edit_data_path))
-        .route_layer(middleware::from_fn(auth));
+        .route_layer(middleware::from_fn_with_state(
+            (state.clone(), Scope::DataWrite),
+            auth,
+        ));
     let post_routes = Router::new()
         .route("/data{*key_path}", post(add_data_path))
-        .route_layer(middleware::from_fn(auth));
+        .route_layer(middleware::from_fn_with_state(
+            (state.clone(), Scope::DataWrite),
+            auth,
+        ));
     let delete_routes = Router::new()
         .route("/data{*key_path}", delete(delete_data_path))
-        .route_layer(middleware::from_fn(auth));
+        .route_layer(middleware::from_fn_with_state(
+            (state.clone(), Scope::DataWrite),
+            auth,
+        ));
+    let patch_routes = Router::new()
+        .route("/data{*key_path}", patch(reorder_data_path))
+        .route_layer(middleware::from_fn_with_state(
+            (state.clone(), Scope::DataWrite),
+            auth,
+        ));
+    let history_routes =
+        Router::new()
+            .route("/history", get(history))
+            .route_layer(middleware::from_fn_with_state(
+                (state.clone(), Scope::DataRead),
+                auth,
+            ));
+    let key_routes = Router::new()
+        .route("/keys", get(list_keys).post(create_key))
+        .route("/keys/{name}", delete(revoke_key))
+        .route("/keys/{name}/rotate", post(rotate_key))
+        .route("/config/reload", post(reload_config))
+        .route_layer(middleware::from_fn_with_state(
+            (state.clone(), Scope::Admin),
+            auth,
+        ));
     let app = get_routes
+        .merge(online_routes)
+        .merge(geoip_routes)
+        .merge(nickname_routes)
+        .merge(render_routes)
+        .merge(history_routes)
+        .merge(key_routes)
         .merge(put_routes)
         .merge(post_routes)
-        .merge(delete_routes);
-    let listener = match tokio::net::TcpListener::bind(format!("0.0.0.0:{}", config.port)).await {
-        Ok(l) => l,
-        Err(e) => {
-            eprintln!("API Crashed due to: {e}");
-            return;
-        }
+        .merge(delete_routes)
+        .merge(patch_routes)
+        .with_state(state);
+    if let Err(e) = listen(&config, app).await {
+        eprintln!("API Crashed due to: {e}");
+    }
+}
+
+async fn listen(config: &Config, app: Router) -> Result<(), Box<dyn Error>> {
+    let acceptor = match &config.tls {
+        Some(tls) => Some(tls.acceptor()?),
+        None => None,
     };
+    let source = if config.trust_proxy {
+        ClientIpSource::XRealIp
+    } else {
+        ClientIpSource::ConnectInfo
+    };
+    let mut servers = JoinSet::new();
+    for ip in &config.bind {
+        let addr = SocketAddr::new(*ip, config.port);
+        let listener = bind(addr).map_err(|e| format!("could not bind {}: {}", addr, e))?;
+        let app = app.clone().layer(source.clone().into_extension());
+        match &acceptor {
+            Some(acceptor) => {
+                let listener = TlsListener::new(listener, acceptor.clone())?;
+                let app = app.layer(middleware::map_request(tls::peer_connect_info));
+                servers.spawn(async move {
+                    axum::serve(
+                        listener,
+                        app.into_make_service_with_connect_info::<TlsPeer>(),
+                    )
+                    .await
+                });
+                println!("Listening on https://{}", addr);
+            }
+            None => {
+                servers.spawn(async move {
+                    axum::serve(
+                        listener,
+                        app.into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .await
+                });
+                println!("Listening on http://{}", addr);
+            }
+        }
+    }
+    if let Some(path) = &config.unix_socket {
+        if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("could not bind {}: {}", path.display(), e))?;
+        // Unix sockets have no peer IP, so they are always behind a proxy.
+        let app = app.clone().layer(ClientIpSource::XRealIp.into_extension());
+        servers.spawn(async move { axum::serve(listener, app.into_make_service()).await });
+        println!("Listening on unix:{}", path.display());
+    }
+    println!("API successfully started");
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
+    Ok(())
+}
 
-    if let Err(e) = {
-        println!("API successfully started");
-        axum::serve(
-            listener,
-            app.into_make_service_with_connect_info::<SocketAddr>(),
-        )
-        .await
-    } {
-        eprintln!("API Crashed due to: {e}");
-        return;
+// IPv6 sockets are made v6-only, otherwise binding "::" also claims the IPv4
+// port on most systems and a separate "0.0.0.0" listener fails.
+fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
     }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
 }
 
-async fn auth(req: Request, next: Next) -> Result<Response, StatusCode> {
-    let config = Config::new().map_err(|e| {
+fn find_key(storage: &dyn Storage, token: &str) -> Result<Option<ApiKey>, StatusCode> {
+    keys::authenticate(storage, token).map_err(|e| {
         eprintln!("{}", e);
         StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    let headers = req
-        .headers()
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|header| header.to_str().ok());
-    let header = if let Some(auth) = headers {
-        auth
+    })
+}
+
+fn too_many_requests(retry_after: Duration) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(
+            header::RETRY_AFTER,
+            retry_after.as_secs().max(1).to_string(),
+        )],
+    )
+        .into_response()
+}
+
+async fn auth(
+    State((state, scope)): State<(AppState, Scope)>,
+    ClientIp(ip): ClientIp,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if let Some(remaining) = state.auth_failures().locked_out(ip) {
+        return Ok(too_many_requests(remaining));
+    }
+    let token = if let Some(token) = bearer_token(req.headers()) {
+        token
     } else {
         return Err(StatusCode::UNAUTHORIZED);
     };
-    if header == format!("Bearer {}", config.api_key) {
-        Ok(next.run(req).await)
-    } else {
-        return Err(StatusCode::UNAUTHORIZED);
+    let key = find_key(state.storage().as_ref(), token)?.ok_or_else(|| {
+        state.auth_failures().record(ip);
+        StatusCode::UNAUTHORIZED
+    })?;
+    state.auth_failures().clear(ip);
+    if !key.has_scope(scope) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if key.paths.is_some() {
+        let Ok(Path(params)) = req.extract_parts::<Path<HashMap<String, String>>>().await else {
+            return Err(StatusCode::BAD_REQUEST);
+        };
+        let key_path = params.get("key_path").ok_or(StatusCode::FORBIDDEN)?;
+        if !key.allows(req.method().as_str(), key_path) {
+            return Err(StatusCode::FORBIDDEN);
+        }
     }
+    req.extensions_mut().insert(key);
+    Ok(next.run(req).await)
 }
 
-async fn data() -> Result<Json<Value>, StatusCode> {
-    let data = BaseDirectories::with_prefix("nameful-api")
-        .find_data_file("data.json")
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(read_json_from_file(&data).map_err(|e| {
-        eprintln!("{}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?))
+async fn rate_limit(
+    State((state, route)): State<(AppState, &'static str)>,
+    ClientIp(ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let config = state.config();
+    let Some(limit) = config.rate_limits.get(route) else {
+        return Ok(next.run(req).await);
+    };
+    let exempt = match bearer_token(req.headers()) {
+        Some(token) => {
+            if let Some(remaining) = state.auth_failures().locked_out(ip) {
+                return Ok(too_many_requests(remaining));
+            }
+            let found = find_key(state.storage().as_ref(), token)?.is_some();
+            if found {
+                state.auth_failures().clear(ip);
+            } else {
+                state.auth_failures().record(ip);
+            }
+            found
+        }
+        None => false,
+    };
+    if !exempt && let Err(retry_after) = state.rate_limiter().check(route, ip, limit) {
+        return Ok(too_many_requests(retry_after));
+    }
+    Ok(next.run(req).await)
 }
 
-async fn data_path(Path(key_path): Path<String>) -> Result<Json<Value>, StatusCode> {
-    let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
-    let data = xdg_dirs
-        .find_data_file("data.json")
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-    let nick = xdg_dirs
-        .find_data_file("nick-cache.json")
+fn load_data(state: &AppState) -> Result<(Arc<dyn Storage>, Value), StatusCode> {
+    let storage = state.storage();
+    let json = storage
+        .load("data")
+        .map_err(|e| {
+            eprintln!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
         .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-    if key_path.len() < 7 {
-        return read_json_from_file(&data)
+    Ok((storage, json))
+}
+
+async fn data(State(state): State<AppState>) -> Result<impl IntoResponse, StatusCode> {
+    let (_, json) = load_data(&state)?;
+    Ok(([(header::ETAG, etag(&json))], Json(json)))
+}
+
+async fn data_path(
+    State(state): State<AppState>,
+    Path(key_path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (storage, data) = load_data(&state)?;
+    let (namespace, pointer) = split_namespace(&key_path);
+    let json = match VirtualDocument::from_name(namespace) {
+        Some(document) => document
+            .resolve(&state, storage.as_ref(), pointer)
+            .await
             .map_err(|e| {
                 eprintln!("{}", e);
                 StatusCode::INTERNAL_SERVER_ERROR
-            })?
-            .pointer(&key_path)
-            .ok_or(StatusCode::NOT_FOUND)
-            .map(|j| Json(j.clone()));
+            })?,
+        None => data.pointer(&key_path).cloned(),
     }
-    match &key_path[1..7] {
-        "nicked" => read_json_from_file(&nick)
-            .map_err(|e| {
-                eprintln!("{}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?
-            .pointer(&key_path[7..])
-            .ok_or(StatusCode::NOT_FOUND)
-            .map(|j| Json(j.clone())),
-        _ => read_json_from_file(&data)
-            .map_err(|e| {
-                eprintln!("{}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?
-            .pointer(&key_path)
-            .ok_or(StatusCode::NOT_FOUND)
-            .map(|j| Json(j.clone())),
+    .ok_or(StatusCode::NOT_FOUND)?;
+    if !params.keys().any(|k| is_query_param(k)) {
+        return Ok(([(header::ETAG, etag(&json))], Json(json)));
+    }
+    let array = json.as_array().ok_or(StatusCode::BAD_REQUEST)?;
+    query_array(array, &params)
+        .map_err(|e| {
+            eprintln!("{}", e);
+            StatusCode::BAD_REQUEST
+        })
+        .map(|j| ([(header::ETAG, etag(&j))], Json(j)))
+}
+
+fn reject_virtual(key_path: &str) -> Result<(), StatusCode> {
+    match VirtualDocument::from_name(split_namespace(key_path).0) {
+        Some(_) => Err(StatusCode::METHOD_NOT_ALLOWED),
+        None => Ok(()),
+    }
+}
+
+fn check_if_match(headers: &HeaderMap, json: &Value) -> Result<(), StatusCode> {
+    let Some(if_match) = headers.get(header::IF_MATCH) else {
+        return Ok(());
+    };
+    let if_match = if_match.to_str().map_err(|_| StatusCode::BAD_REQUEST)?;
+    if etag_matches(if_match, json) {
+        Ok(())
+    } else {
+        Err(StatusCode::PRECONDITION_FAILED)
     }
 }
 
 async fn edit_data_path(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Extension(key): Extension<ApiKey>,
+    headers: HeaderMap,
     Path(key_path): Path<String>,
     Json(body): Json<Value>,
 ) -> Result<Json<Value>, StatusCode> {
-    let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
-    let data = xdg_dirs
-        .find_data_file("data.json")
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-    let json = &mut read_json_from_file(&data).map_err(|e| {
-        eprintln!("{}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    if let Err(e) = backup(json) {
-        eprintln!("{}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    }
+    reject_virtual(&key_path)?;
+    let _guard = state.lock_data();
+    let (storage, mut data) = load_data(&state)?;
+    let original = data.clone();
+    let json = &mut data;
+    check_if_match(
+        &headers,
+        json.pointer(&key_path).ok_or(StatusCode::NOT_FOUND)?,
+    )?;
     let value: &mut Value = json.pointer_mut(&key_path).ok_or(StatusCode::NOT_FOUND)?;
-    *value = body;
-    write_json_to_file(json, &data)
+    let before = std::mem::replace(value, body.clone());
+    storage
+        .update("data", &original, json)
+        .and_then(|_| audit(ip, &key.name, "PUT", &key_path, before, body))
         .map_err(|e| {
             eprintln!("{}", e);
             StatusCode::INTERNAL_SERVER_ERROR
@@ -192,31 +475,149 @@ async fn edit_data_path(
         .map(|s| Json(json!({"success":s})))
 }
 
+fn array_insert_target(
+    json: &Value,
+    key_path: &str,
+) -> Result<(String, Option<usize>), StatusCode> {
+    if json.pointer(key_path).is_some_and(|v| v.is_array()) {
+        return Ok((key_path.to_string(), None));
+    }
+    let (parent, token) = key_path.rsplit_once('/').ok_or(StatusCode::BAD_REQUEST)?;
+    let Some(array) = json.pointer(parent).and_then(|v| v.as_array()) else {
+        return Err(match json.pointer(key_path) {
+            Some(_) => StatusCode::BAD_REQUEST,
+            None => StatusCode::NOT_FOUND,
+        });
+    };
+    let index = match token {
+        "-" => array.len(),
+        _ => token.parse().map_err(|_| StatusCode::BAD_REQUEST)?,
+    };
+    if index > array.len() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok((parent.to_string(), Some(index)))
+}
+
+fn insert_into_array(
+    json: &mut Value,
+    array_path: &str,
+    index: Option<usize>,
+    body: Value,
+) -> Result<String, StatusCode> {
+    let array = json
+        .pointer_mut(array_path)
+        .and_then(|v| v.as_array_mut())
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let index = index.unwrap_or(array.len());
+    array.insert(index, body);
+    Ok(format!("{}/{}", array_path, index))
+}
+
 async fn add_data_path(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Extension(key): Extension<ApiKey>,
+    headers: HeaderMap,
     Path(key_path): Path<String>,
     Json(body): Json<Value>,
 ) -> Result<Json<Value>, StatusCode> {
-    let data = BaseDirectories::with_prefix("nameful-api")
-        .find_data_file("data.json")
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-    let json = &mut read_json_from_file(&data).map_err(|e| {
-        eprintln!("{}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    if let Err(e) = backup(json) {
-        eprintln!("{}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    reject_virtual(&key_path)?;
+    let _guard = state.lock_data();
+    let (storage, mut data) = load_data(&state)?;
+    let original = data.clone();
+    let json = &mut data;
+    let (array_path, index) = array_insert_target(json, &key_path)?;
+    check_if_match(
+        &headers,
+        json.pointer(&array_path)
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?,
+    )?;
+    let pointer = insert_into_array(json, &array_path, index, body.clone())?;
+    storage
+        .update("data", &original, json)
+        .and_then(|_| audit(ip, &key.name, "POST", &pointer, Value::Null, body))
+        .map_err(|e| {
+            eprintln!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+        .map(|s| Json(json!({"success":s})))
+}
+
+async fn reorder_data_path(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Extension(key): Extension<ApiKey>,
+    headers: HeaderMap,
+    Path(key_path): Path<String>,
+    Json(body): Json<Value>,
+) -> Result<Json<Value>, StatusCode> {
+    reject_virtual(&key_path)?;
+    let _guard = state.lock_data();
+    let (storage, mut data) = load_data(&state)?;
+    let original = data.clone();
+    let json = &mut data;
+    let (op, other) = match body["op"].as_str() {
+        Some("move") => ("move", body["from"].as_str()),
+        Some("swap") => ("swap", body["with"].as_str()),
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let other = other.ok_or(StatusCode::BAD_REQUEST)?;
+    reject_virtual(other)?;
+    if !key.allows("PATCH", other) {
+        return Err(StatusCode::FORBIDDEN);
     }
-    let value: &mut Value = json.pointer_mut(&key_path).ok_or(StatusCode::NOT_FOUND)?;
-    if value.is_array() {
-        let _ = value
-            .as_array_mut()
-            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?
-            .push(body);
-    } else {
+    let (parent, _) = key_path.rsplit_once('/').ok_or(StatusCode::BAD_REQUEST)?;
+    let (other_parent, _) = other.rsplit_once('/').ok_or(StatusCode::BAD_REQUEST)?;
+    if pointer_starts_with(&key_path, other) || pointer_starts_with(other, &key_path) {
         return Err(StatusCode::BAD_REQUEST);
     }
-    write_json_to_file(json, &data)
+    let mut containers = vec![parent.to_string()];
+    if other_parent != parent {
+        containers.push(other_parent.to_string());
+    }
+    check_if_match(&headers, json.pointer(parent).ok_or(StatusCode::NOT_FOUND)?)?;
+    let before = containers
+        .iter()
+        .map(|c| json.pointer(c).cloned().ok_or(StatusCode::NOT_FOUND))
+        .collect::<Result<Vec<Value>, StatusCode>>()?;
+    if op == "move" {
+        let value = json
+            .remove(other)
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                None
+            })
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let (array_path, index) = array_insert_target(json, &key_path)?;
+        if array_path != parent {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        insert_into_array(json, &array_path, index, value)?;
+    } else {
+        let a = json
+            .pointer(&key_path)
+            .cloned()
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let b = json.pointer(other).cloned().ok_or(StatusCode::NOT_FOUND)?;
+        *json.pointer_mut(&key_path).ok_or(StatusCode::NOT_FOUND)? = b;
+        *json.pointer_mut(other).ok_or(StatusCode::NOT_FOUND)? = a;
+    }
+    let after = containers
+        .iter()
+        .map(|c| json.pointer(c).cloned().unwrap_or(Value::Null))
+        .collect::<Vec<Value>>();
+    storage
+        .update("data", &original, json)
+        .and_then(|_| {
+            containers
+                .iter()
+                .zip(before)
+                .zip(after)
+                .try_for_each(|((container, before), after)| {
+                    audit(ip, &key.name, "PATCH", container, before, after)
+                })
+        })
         .map_err(|e| {
             eprintln!("{}", e);
             StatusCode::INTERNAL_SERVER_ERROR
@@ -224,29 +625,157 @@ async fn add_data_path(
         .map(|s| Json(json!({"success":s})))
 }
 
-async fn delete_data_path(Path(key_path): Path<String>) -> Result<Json<Value>, StatusCode> {
-    let data = BaseDirectories::with_prefix("nameful-api")
-        .find_data_file("data.json")
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-    let json = &mut read_json_from_file(&data).map_err(|e| {
-        eprintln!("{}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    if let Err(e) = backup(json) {
-        eprintln!("{}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    }
-    if let None = json.remove(&key_path).unwrap_or_else(|e| {
+async fn delete_data_path(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Extension(key): Extension<ApiKey>,
+    headers: HeaderMap,
+    Path(key_path): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    reject_virtual(&key_path)?;
+    let _guard = state.lock_data();
+    let (storage, mut data) = load_data(&state)?;
+    let original = data.clone();
+    let json = &mut data;
+    check_if_match(
+        &headers,
+        json.pointer(&key_path).ok_or(StatusCode::NOT_FOUND)?,
+    )?;
+    let Some(before) = json.remove(&key_path).unwrap_or_else(|e| {
         eprintln!("{}", e);
         None
-    }) {
+    }) else {
         return Err(StatusCode::NOT_FOUND);
+    };
+    storage
+        .update("data", &original, json)
+        .and_then(|_| audit(ip, &key.name, "DELETE", &key_path, before, Value::Null))
+        .map_err(|e| {
+            eprintln!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+        .map(|s| Json(json!({"success":s})))
+}
+
+async fn history(Query(params): Query<HashMap<String, String>>) -> Result<Json<Value>, StatusCode> {
+    let since = params
+        .get("since")
+        .map(|s| s.parse::<i64>())
+        .transpose()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let until = params
+        .get("until")
+        .map(|s| s.parse::<i64>())
+        .transpose()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    audit_history(params.get("path").map(|p| p.as_str()), since, until)
+        .map_err(|e| {
+            eprintln!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+        .map(Json)
+}
+
+async fn list_keys(State(state): State<AppState>) -> Result<Json<Value>, StatusCode> {
+    let storage = state.storage();
+    keys::load_keys(storage.as_ref())
+        .map_err(|e| {
+            eprintln!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+        .map(|keys| {
+            Json(json!(
+                keys.iter()
+                    .map(|k| {
+                        json!({
+                            "name":k.name,
+                            "scopes":k.scopes,
+                            "created":k.created,
+                            "paths":k.paths,
+                            "expires":k.expires,
+                            "grace_until":k.previous.iter().map(|r| r.expires).max(),
+                        })
+                    })
+                    .collect::<Vec<Value>>()
+            ))
+        })
+}
+
+async fn create_key(
+    State(state): State<AppState>,
+    Json(body): Json<Value>,
+) -> Result<Json<Value>, StatusCode> {
+    let storage = state.storage();
+    let name = body["name"].as_str().ok_or(StatusCode::BAD_REQUEST)?;
+    let scopes: Vec<Scope> =
+        serde_json::from_value(body["scopes"].clone()).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let paths: Option<Vec<PathRule>> =
+        serde_json::from_value(body["paths"].clone()).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let expires: Option<i64> =
+        serde_json::from_value(body["expires"].clone()).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if keys::load_keys(storage.as_ref())
+        .map_err(|e| {
+            eprintln!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .iter()
+        .any(|k| k.name == name)
+    {
+        return Err(StatusCode::CONFLICT);
     }
-    write_json_to_file(json, &data)
+    keys::create_key(storage.as_ref(), name, scopes, paths, expires)
         .map_err(|e| {
             eprintln!("{}", e);
             StatusCode::INTERNAL_SERVER_ERROR
         })
+        .map(|key| Json(json!({"name":name,"key":key})))
+}
+
+async fn revoke_key(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    let storage = state.storage();
+    match keys::revoke_key(storage.as_ref(), &name) {
+        Ok(true) => Ok(Json(json!({"success":()}))),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn rotate_key(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let config = state.config();
+    let storage = state.storage();
+    let expires = params
+        .get("expires")
+        .map(|e| e.parse::<i64>())
+        .transpose()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let grace_period = i64::from(config.key_grace_period) * 60 * 60;
+    match keys::rotate_key(storage.as_ref(), &name, grace_period, expires) {
+        Ok(Some(key)) => Ok(Json(json!({"name":name,"key":key}))),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn reload_config(State(state): State<AppState>) -> Result<Json<Value>, StatusCode> {
+    state
+        .reload()
+        .map_err(|e| {
+            eprintln!("Config reload failed, keeping current config: {}", e);
+            StatusCode::UNPROCESSABLE_ENTITY
+        })
         .map(|s| Json(json!({"success":s})))
 }
 
@@ -329,12 +858,9 @@ async fn render(
     Ok((headers, body))
 }
 
-async fn propaganda() -> Result<Json<Value>, StatusCode> {
-    let config = Config::new().map_err(|e| {
-        eprintln!("{}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    dir_to_json(config.propaganda_path)
+async fn propaganda(State(state): State<AppState>) -> Result<Json<Value>, StatusCode> {
+    let config = state.config();
+    dir_to_json(config.propaganda_path.clone())
         .map_err(|e| {
             eprintln!("{}", e);
             StatusCode::INTERNAL_SERVER_ERROR
@@ -342,26 +868,185 @@ async fn propaganda() -> Result<Json<Value>, StatusCode> {
         .map(|j| Json(j))
 }
 
-async fn online() -> Result<Json<Value>, StatusCode> {
-    fetch_osm_info()
-        .map_err(|e| {
-            eprintln!("{}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })
-        .map(|j| {
-            j.pointer("/players")
-                .map(|j| j.clone())
-                .ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+async fn online(State(state): State<AppState>) -> Result<Json<Value>, StatusCode> {
+    let config = state.config();
+    let mut players = Vec::new();
+    let mut servers = serde_json::Map::new();
+    for target in &config.servers {
+        let Some(cached) = state.status().get(&target.name) else {
+            continue;
+        };
+        players.extend(
+            cached
+                .status
+                .players
+                .iter()
+                .map(|player| json!({"username": player, "server": target.name})),
+        );
+        servers.insert(
+            target.name.clone(),
+            json!({
+                "last_updated": cached.last_updated,
+                "age": cached.age(),
+                "stale": cached.is_stale(config.status_interval),
+            }),
+        );
+    }
+    if servers.is_empty() && !config.servers.is_empty() {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    Ok(Json(json!({"players": players, "servers": servers})))
+}
+
+async fn server_online(
+    State(state): State<AppState>,
+    Path(server): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    let config = state.config();
+    if !config.servers.iter().any(|target| target.name == server) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let cached = state
+        .status()
+        .get(&server)
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(Json(json!({
+        "server": server,
+        "players": cached.status.players,
+        "partial": cached.is_partial(),
+        "last_updated": cached.last_updated,
+        "age": cached.age(),
+        "stale": cached.is_stale(config.status_interval),
+    })))
+}
+
+async fn player_online(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    let config = state.config();
+    let statuses: Vec<_> = config
+        .servers
+        .iter()
+        .filter_map(|target| Some((target, state.status().get(&target.name)?)))
+        .collect();
+    if statuses.is_empty() && !config.servers.is_empty() {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    let server = statuses
+        .iter()
+        .find(|(_, cached)| cached.is_online(&username) == Some(true))
+        .map(|(target, _)| target.name.clone());
+    // Unknown when the player could be outside a partial sample.
+    let unknown = statuses
+        .iter()
+        .any(|(_, cached)| cached.is_online(&username).is_none());
+    let online = match server {
+        Some(_) => Some(true),
+        None if unknown => None,
+        None => Some(false),
+    };
+    Ok(Json(json!({
+        "username": username,
+        "online": online,
+        "server": server,
+        "stale": statuses
+            .iter()
+            .any(|(_, cached)| cached.is_stale(config.status_interval)),
+    })))
+}
+
+fn status_json(cached: &CachedStatus, interval: u32) -> Value {
+    json!({
+        "online": cached
+            .status
+            .online
+            .map_or(cached.status.players.len(), |online| online as usize),
+        "max_players": cached.status.max_players,
+        "motd": cached.status.motd,
+        "version": cached.status.version,
+        "players": cached.status.players,
+        "partial": cached.is_partial(),
+        "latency_ms": cached.latency_ms,
+        "last_updated": cached.last_updated,
+        "age": cached.age(),
+        "stale": cached.is_stale(interval),
+    })
+}
+
+async fn status(State(state): State<AppState>) -> Json<Value> {
+    let config = state.config();
+    let statuses: serde_json::Map<String, Value> = config
+        .servers
+        .iter()
+        .filter_map(|target| {
+            let cached = state.status().get(&target.name)?;
+            Some((
+                target.name.clone(),
+                status_json(&cached, config.status_interval),
+            ))
         })
-        .flatten()
-        .map(|j| Json(j))
+        .collect();
+    Json(Value::Object(statuses))
+}
+
+async fn server_status(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    let config = state.config();
+    if !config.servers.iter().any(|target| target.name == name) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let cached = state
+        .status()
+        .get(&name)
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(Json(status_json(&cached, config.status_interval)))
+}
+
+async fn player_sessions(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    let log = load_session_log(&state)?;
+    Ok(Json(json!(sessions::player_sessions(&log, &username))))
+}
+
+async fn player_playtime(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    let log = load_session_log(&state)?;
+    Ok(Json(json!({
+        "username": username,
+        "seconds": sessions::playtime(&log, &username),
+        "sessions": sessions::player_sessions(&log, &username).len(),
+    })))
+}
+
+async fn player_peaks(State(state): State<AppState>) -> Result<Json<Value>, StatusCode> {
+    let log = load_session_log(&state)?;
+    let peaks: Vec<Value> = log
+        .peaks
+        .iter()
+        .map(|(date, peak)| json!({"date": date, "peak": peak}))
+        .collect();
+    Ok(Json(json!(peaks)))
 }
 
-async fn ip(XRealIp(ip): XRealIp) -> Json<Value> {
+fn load_session_log(state: &AppState) -> Result<sessions::SessionLog, StatusCode> {
+    sessions::load_sessions(state.storage().as_ref()).map_err(|e| {
+        eprintln!("{}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn ip(ClientIp(ip): ClientIp) -> Json<Value> {
     Json(json!({"ip":ip}))
 }
 
-async fn geoip(XRealIp(ip): XRealIp) -> Result<Json<Value>, StatusCode> {
+async fn geoip(ClientIp(ip): ClientIp) -> Result<Json<Value>, StatusCode> {
     let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
     let db_path = xdg_dirs
         .find_data_file("GeoLite2-City.mmdb")
@@ -391,15 +1076,11 @@ async fn geoip_with_ip(Path(ip): Path<String>) -> Result<Json<Value>, StatusCode
         .map(|j| Json(j))
 }
 
-async fn splash(XRealIp(ip): XRealIp) -> Result<Json<Value>, StatusCode> {
-    let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
-    let data = xdg_dirs
-        .find_data_file("data.json")
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-    let json = read_json_from_file(&data).map_err(|e| {
-        eprintln!("{}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+async fn splash(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+) -> Result<Json<Value>, StatusCode> {
+    let (_, json) = load_data(&state)?;
     let splashes = json
         .pointer("/splashes")
         .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
@@ -416,13 +1097,108 @@ async fn splash(XRealIp(ip): XRealIp) -> Result<Json<Value>, StatusCode> {
     }))
 }
 
-async fn nickname(Path(username): Path<String>) -> Result<Json<Value>, Json<Value>> {
-    let config = Config::new().map_err(|e| {
-        eprintln!("{}", e);
-        Json(json!({"nickname":username}))
-    })?;
-    get_nickname(&config, &username)
-        .await
-        .map_err(|_| Json(json!({"nickname":username})))
-        .map(|j| Json(json!({"nickname":j})))
+async fn nickname(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(username): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let config = state.config();
+    let fresh = params.get("fresh").is_some_and(|fresh| fresh == "true");
+    if fresh {
+        if let Some(remaining) = state.auth_failures().locked_out(ip) {
+            return Ok(too_many_requests(remaining));
+        }
+        let token = bearer_token(&headers).ok_or(StatusCode::UNAUTHORIZED)?;
+        if find_key(state.storage().as_ref(), token)?.is_none() {
+            state.auth_failures().record(ip);
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        state.auth_failures().clear(ip);
+    }
+    match nicknames::nickname(config, state.storage(), &username, fresh).await {
+        Ok(Some(nickname)) => Ok(Json(json!({"nickname":nickname})).into_response()),
+        Ok(None) => Ok(Json(json!({"nickname":username,"nickname_status":"none"})).into_response()),
+        Err(..) => {
+            Ok(Json(json!({"nickname":username,"nickname_status":"fallback"})).into_response())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    async fn get_as(app: Router, uri: &str, peer: &str, real_ip: &str) -> Response {
+        let mut request = Request::builder()
+            .uri(uri)
+            .header("x-real-ip", real_ip)
+            .body(Body::empty())
+            .unwrap();
+        let peer = SocketAddr::new(peer.parse().unwrap(), 40000);
+        request
+            .extensions_mut()
+            .insert(axum::extract::ConnectInfo(peer));
+        app.layer(ClientIpSource::ConnectInfo.into_extension())
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    fn history_route(state: &AppState) -> Router {
+        Router::new()
+            .route("/history", get(|| async {}))
+            .route_layer(middleware::from_fn_with_state(
+                (state.clone(), Scope::DataRead),
+                auth,
+            ))
+    }
+
+    #[tokio::test]
+    async fn ipv4_and_ipv6_wildcards_bind_the_same_port() {
+        let v4 = bind("0.0.0.0:0".parse().unwrap()).unwrap();
+        let port = v4.local_addr().unwrap().port();
+        let v6 = bind(SocketAddr::new("::".parse().unwrap(), port)).unwrap();
+        assert_eq!(v6.local_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test]
+    async fn spoofed_real_ip_headers_are_ignored_without_a_proxy() {
+        let state = AppState::new(Config::default(), ConfigOverrides::default()).unwrap();
+        let victim = "192.0.2.36";
+        for _ in 0..keys::MAX_FAILED_ATTEMPTS {
+            state.auth_failures().record(victim.parse().unwrap());
+        }
+        let response = get_as(history_route(&state), "/history", "192.0.2.37", victim).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = get_as(history_route(&state), "/history", victim, "192.0.2.37").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn locked_out_clients_get_retry_after() {
+        let state = AppState::new(Config::default(), ConfigOverrides::default()).unwrap();
+        let ip = "192.0.2.35";
+        for _ in 0..keys::MAX_FAILED_ATTEMPTS {
+            state.auth_failures().record(ip.parse().unwrap());
+        }
+        let history = history_route(&state);
+        let nickname = Router::new()
+            .route("/nickname/{username}", get(nickname))
+            .with_state(state);
+        for response in [
+            get_as(history, "/history", ip, ip).await,
+            get_as(nickname, "/nickname/someone?fresh=true", ip, ip).await,
+        ] {
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!(retry_after >= 1);
+        }
+    }
 }
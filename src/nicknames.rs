@@ -0,0 +1,98 @@
+use crate::{Config, get_nickname, storage::Storage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    sync::{Arc, LazyLock, Mutex},
+    time::SystemTime,
+};
+
+static CACHE_LOCK: Mutex<()> = Mutex::new(());
+static REFRESHING: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NicknameEntry {
+    /// `None` caches that the player has no nickname.
+    pub nickname: Option<Value>,
+    pub fetched: i64,
+}
+
+fn now() -> i64 {
+    let now: DateTime<Utc> = SystemTime::now().into();
+    now.timestamp()
+}
+
+pub fn load_nicknames(
+    storage: &dyn Storage,
+) -> Result<HashMap<String, NicknameEntry>, Box<dyn Error>> {
+    match storage.load("nicknames")? {
+        Some(entries) => Ok(serde_json::from_value(entries)?),
+        None => Ok(HashMap::new()),
+    }
+}
+
+pub fn store_nicknames(
+    storage: &dyn Storage,
+    nicknames: impl IntoIterator<Item = (String, Option<Value>)>,
+) -> Result<(), Box<dyn Error>> {
+    let _guard = CACHE_LOCK.lock().map_err(|e| e.to_string())?;
+    let mut entries = load_nicknames(storage)?;
+    let fetched = now();
+    for (username, nickname) in nicknames {
+        entries.insert(username.to_lowercase(), NicknameEntry { nickname, fetched });
+    }
+    storage.save("nicknames", &json!(entries))
+}
+
+pub async fn refresh(
+    config: &Config,
+    storage: &dyn Storage,
+    username: &str,
+) -> Result<Option<Value>, Box<dyn Error>> {
+    let nickname = get_nickname(config, username).await?;
+    store_nicknames(storage, [(username.to_string(), nickname.clone())])?;
+    Ok(nickname)
+}
+
+fn spawn_refresh(config: Arc<Config>, storage: Arc<dyn Storage>, username: &str) {
+    let key = username.to_lowercase();
+    let Ok(mut refreshing) = REFRESHING.lock() else {
+        return;
+    };
+    if !refreshing.insert(key.clone()) {
+        return;
+    }
+    drop(refreshing);
+    let username = username.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = refresh(&config, storage.as_ref(), &username)
+            .await
+            .map_err(|e| e.to_string())
+        {
+            println!("Nickname refresh for {} failed: {}", username, e);
+        }
+        if let Ok(mut refreshing) = REFRESHING.lock() {
+            refreshing.remove(&key);
+        }
+    });
+}
+
+pub async fn nickname(
+    config: Arc<Config>,
+    storage: Arc<dyn Storage>,
+    username: &str,
+    fresh: bool,
+) -> Result<Option<Value>, Box<dyn Error>> {
+    if !fresh {
+        let entry = load_nicknames(storage.as_ref())?.remove(&username.to_lowercase());
+        if let Some(entry) = entry {
+            if now() - entry.fetched >= i64::from(config.nickname_ttl) * 3600 {
+                spawn_refresh(config, storage, username);
+            }
+            return Ok(entry.nickname);
+        }
+    }
+    refresh(&config, storage.as_ref(), username).await
+}
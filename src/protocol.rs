@@ -0,0 +1,192 @@
+//! Wire format of the os-mc.net status port.
+//!
+//! A response is a single frame: a 4-byte big-endian length giving the number
+//! of UTF-16 code units in the payload, followed by that many code units
+//! encoded as UTF-16BE. The payload is a JSON object describing the server.
+//! Bytes after the payload are ignored.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::error::Error;
+
+const HEADER_LEN: usize = 4;
+
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
+pub struct ServerStatus {
+    #[serde(default)]
+    pub players: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub online: Option<u32>,
+    #[serde(default, alias = "maxPlayers", skip_serializing_if = "Option::is_none")]
+    pub max_players: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+pub fn decode_frame(frame: &[u8]) -> Result<ServerStatus, Box<dyn Error>> {
+    let header: [u8; HEADER_LEN] = frame
+        .get(..HEADER_LEN)
+        .ok_or("frame is shorter than its header")?
+        .try_into()?;
+    let units = u32::from_be_bytes(header) as usize;
+    if units == 0 {
+        return Err("frame has an empty payload".into());
+    }
+    let payload = units
+        .checked_mul(2)
+        .and_then(|len| frame.get(HEADER_LEN..HEADER_LEN + len))
+        .ok_or(format!(
+            "frame declares {} code units but only {} bytes follow the header",
+            units,
+            frame.len() - HEADER_LEN
+        ))?;
+    let text = char::decode_utf16(
+        payload
+            .chunks_exact(2)
+            .map(|unit| u16::from_be_bytes([unit[0], unit[1]])),
+    )
+    .collect::<Result<String, _>>()?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+pub fn encode_frame(status: &ServerStatus) -> Result<Vec<u8>, Box<dyn Error>> {
+    let units: Vec<u16> = serde_json::to_string(status)?.encode_utf16().collect();
+    let mut frame = Vec::with_capacity(HEADER_LEN + units.len() * 2);
+    frame.extend_from_slice(&u32::try_from(units.len())?.to_be_bytes());
+    for unit in units {
+        frame.extend_from_slice(&unit.to_be_bytes());
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn frame_from_units(units: &[u16]) -> Vec<u8> {
+        let mut frame = (units.len() as u32).to_be_bytes().to_vec();
+        for unit in units {
+            frame.extend_from_slice(&unit.to_be_bytes());
+        }
+        frame
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let status = ServerStatus {
+            players: vec![String::from("Steve Jobs"), String::from("𝔄lex 🎮")],
+            online: Some(2),
+            max_players: Some(20),
+            motd: Some(String::from("  Welcome to the  server 🌍 ")),
+            version: Some(String::from("1.21.4")),
+            extra: Map::from_iter([(String::from("tps"), Value::from(19.5))]),
+        };
+        let frame = encode_frame(&status).unwrap();
+        let units = serde_json::to_string(&status)
+            .unwrap()
+            .encode_utf16()
+            .count();
+        assert_eq!(frame[..HEADER_LEN], (units as u32).to_be_bytes());
+        assert_eq!(frame.len(), HEADER_LEN + units * 2);
+        assert_eq!(decode_frame(&frame).unwrap(), status);
+    }
+
+    #[test]
+    fn bytes_after_the_payload_are_ignored() {
+        let mut frame = encode_frame(&ServerStatus::default()).unwrap();
+        frame.push(0xff);
+        assert_eq!(decode_frame(&frame).unwrap(), ServerStatus::default());
+    }
+
+    #[test]
+    fn empty_and_short_frames_are_rejected() {
+        assert!(decode_frame(&[]).is_err());
+        assert!(decode_frame(&[0, 0, 0]).is_err());
+        assert!(decode_frame(&[0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let frame = encode_frame(&ServerStatus::default()).unwrap();
+        assert!(decode_frame(&frame[..frame.len() - 2]).is_err());
+        assert!(decode_frame(&frame[..frame.len() - 1]).is_err());
+        let mut frame = frame_from_units(&"{}".encode_utf16().collect::<Vec<_>>());
+        frame[3] = 3;
+        frame.push(0);
+        assert!(decode_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn lone_surrogates_are_rejected() {
+        let mut units: Vec<u16> = "{\"motd\":\"".encode_utf16().collect();
+        units.push(0xd800);
+        units.extend("\"}".encode_utf16());
+        assert!(decode_frame(&frame_from_units(&units)).is_err());
+    }
+
+    fn server_status() -> impl Strategy<Value = ServerStatus> {
+        let extra = prop::collection::btree_map(
+            "x_[a-z0-9]{0,8}",
+            prop_oneof![
+                any::<String>().prop_map(Value::from),
+                any::<i64>().prop_map(Value::from),
+                any::<bool>().prop_map(Value::from),
+            ],
+            0..4,
+        );
+        (
+            prop::collection::vec(any::<String>(), 0..16),
+            any::<Option<u32>>(),
+            any::<Option<u32>>(),
+            any::<Option<String>>(),
+            any::<Option<String>>(),
+            extra,
+        )
+            .prop_map(|(players, online, max_players, motd, version, extra)| {
+                ServerStatus {
+                    players,
+                    online,
+                    max_players,
+                    motd,
+                    version,
+                    extra: Map::from_iter(extra),
+                }
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_statuses_round_trip(status in server_status()) {
+            let frame = encode_frame(&status).unwrap();
+            prop_assert_eq!(decode_frame(&frame).unwrap(), status);
+        }
+
+        #[test]
+        fn strict_prefixes_are_rejected(status in server_status()) {
+            let frame = encode_frame(&status).unwrap();
+            for len in 0..frame.len() {
+                prop_assert!(decode_frame(&frame[..len]).is_err());
+            }
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+            let _ = decode_frame(&bytes);
+        }
+
+        #[test]
+        fn arbitrary_payloads_never_panic(
+            units in 0u32..64,
+            payload in prop::collection::vec(any::<u8>(), 0..128),
+        ) {
+            let mut frame = units.to_be_bytes().to_vec();
+            frame.extend(payload);
+            let _ = decode_frame(&frame);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub per_minute: u32,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RateLimits {
+    pub render: RateLimit,
+    pub nickname: RateLimit,
+    pub geoip: RateLimit,
+    pub online: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> RateLimits {
+        RateLimits {
+            render: RateLimit {
+                capacity: 30,
+                per_minute: 30,
+            },
+            nickname: RateLimit {
+                capacity: 30,
+                per_minute: 30,
+            },
+            geoip: RateLimit {
+                capacity: 60,
+                per_minute: 60,
+            },
+            online: RateLimit {
+                capacity: 60,
+                per_minute: 60,
+            },
+        }
+    }
+}
+
+impl RateLimits {
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, RateLimit)> {
+        [
+            ("render", self.render),
+            ("nickname", self.nickname),
+            ("geoip", self.geoip),
+            ("online", self.online),
+        ]
+        .into_iter()
+    }
+
+    pub fn get(&self, route: &str) -> Option<RateLimit> {
+        match route {
+            "render" => Some(self.render),
+            "nickname" => Some(self.nickname),
+            "geoip" => Some(self.geoip),
+            "online" => Some(self.online),
+            _ => None,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    limit: RateLimit,
+}
+
+impl Bucket {
+    fn rate(&self) -> f64 {
+        f64::from(self.limit.per_minute) / 60.0
+    }
+
+    fn refilled(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * self.rate()).min(f64::from(self.limit.capacity))
+    }
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<(&'static str, IpAddr), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn check(&self, route: &'static str, ip: IpAddr, limit: RateLimit) -> Result<(), Duration> {
+        self.check_at(route, ip, limit, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        route: &'static str,
+        ip: IpAddr,
+        limit: RateLimit,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return Ok(());
+        };
+        if buckets.len() >= MAX_TRACKED_BUCKETS {
+            buckets.retain(|_, bucket| bucket.refilled(now) < f64::from(bucket.limit.capacity));
+            let keep = MAX_TRACKED_BUCKETS / 2;
+            if buckets.len() > keep {
+                let mut updated: Vec<Instant> = buckets.values().map(|b| b.updated).collect();
+                updated.sort_unstable();
+                let cutoff = updated[updated.len() - keep];
+                buckets.retain(|_, bucket| bucket.updated >= cutoff);
+            }
+        }
+        let bucket = buckets.entry((route, ip)).or_insert(Bucket {
+            tokens: f64::from(limit.capacity),
+            updated: now,
+            limit,
+        });
+        bucket.limit = limit;
+        bucket.tokens = bucket.refilled(now);
+        bucket.updated = now;
+        let rate = bucket.rate();
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if rate > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        } else {
+            Err(Duration::from_secs(60))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        capacity: 2,
+        per_minute: 60,
+    };
+
+    #[test]
+    fn buckets_drain_and_report_retry_after() {
+        let limiter = RateLimiter::default();
+        let ip = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+        assert!(limiter.check_at("render", ip, LIMIT, now).is_ok());
+        assert!(limiter.check_at("render", ip, LIMIT, now).is_ok());
+        let retry_after = limiter.check_at("render", ip, LIMIT, now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+        assert!(limiter.check_at("geoip", ip, LIMIT, now).is_ok());
+        assert!(
+            limiter
+                .check_at("render", "192.0.2.2".parse().unwrap(), LIMIT, now)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn buckets_refill_over_time_up_to_capacity() {
+        let limiter = RateLimiter::default();
+        let ip = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..2 {
+            limiter.check_at("render", ip, LIMIT, now).unwrap();
+        }
+        let later = now + Duration::from_millis(500);
+        let retry_after = limiter.check_at("render", ip, LIMIT, later).unwrap_err();
+        assert!((retry_after.as_secs_f64() - 0.5).abs() < 1e-6);
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.check_at("render", ip, LIMIT, later).is_ok());
+        assert!(limiter.check_at("render", ip, LIMIT, later).is_err());
+        let much_later = now + Duration::from_secs(60);
+        assert!(limiter.check_at("render", ip, LIMIT, much_later).is_ok());
+        assert!(limiter.check_at("render", ip, LIMIT, much_later).is_ok());
+        assert!(limiter.check_at("render", ip, LIMIT, much_later).is_err());
+    }
+
+    #[test]
+    fn eviction_keeps_the_limiter_bounded() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        for i in 0..MAX_TRACKED_BUCKETS as u32 + 1 {
+            let ip = IpAddr::from(i.to_be_bytes());
+            let at = now + Duration::from_micros(u64::from(i));
+            limiter.check_at("render", ip, LIMIT, at).unwrap();
+        }
+        assert!(limiter.buckets.lock().unwrap().len() <= MAX_TRACKED_BUCKETS / 2 + 1);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::storage::Storage;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::BTreeMap, error::Error, time::SystemTime};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Session {
+    pub username: String,
+    #[serde(default)]
+    pub server: String,
+    pub joined: i64,
+    pub left: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SessionLog {
+    pub sessions: Vec<Session>,
+    pub peaks: BTreeMap<String, usize>,
+    pub last_poll: Option<i64>,
+}
+
+fn now() -> i64 {
+    let now: DateTime<Utc> = SystemTime::now().into();
+    now.timestamp()
+}
+
+pub fn load_sessions(storage: &dyn Storage) -> Result<SessionLog, Box<dyn Error>> {
+    match storage.load("sessions")? {
+        Some(log) => Ok(serde_json::from_value(log)?),
+        None => Ok(SessionLog::default()),
+    }
+}
+
+// If the API itself was not polling, nobody is known to have stayed online,
+// so open sessions end at the last poll before the gap.
+fn close_after_gap(log: &mut SessionLog, now: i64, interval: u32) {
+    let Some(last_poll) = log.last_poll else {
+        return;
+    };
+    if now - last_poll <= 2 * i64::from(interval) {
+        return;
+    }
+    for session in log.sessions.iter_mut().filter(|s| s.left.is_none()) {
+        session.left = Some(last_poll.max(session.joined));
+    }
+}
+
+pub fn record_players(
+    storage: &dyn Storage,
+    online: &[(String, String)],
+    count: usize,
+    down: &[(String, i64)],
+    interval: u32,
+) -> Result<(), Box<dyn Error>> {
+    let mut log = load_sessions(storage)?;
+    let now = now();
+    close_after_gap(&mut log, now, interval);
+    log.last_poll = Some(now);
+    let is_session = |session: &Session, server: &str, player: &str| {
+        session.server == server && session.username.eq_ignore_ascii_case(player)
+    };
+    let online: Vec<&(String, String)> = online
+        .iter()
+        .filter(|(server, _)| !down.iter().any(|(d, _)| d == server))
+        .collect();
+    for session in log.sessions.iter_mut().filter(|s| s.left.is_none()) {
+        // A server that could not be polled is treated as empty since its last
+        // successful poll, so an outage is not counted as playtime.
+        if let Some((_, last_seen)) = down.iter().find(|(d, _)| *d == session.server) {
+            session.left = Some((*last_seen).max(session.joined));
+        } else if !online
+            .iter()
+            .any(|(server, player)| is_session(session, server, player))
+        {
+            session.left = Some(now);
+        }
+    }
+    for (server, player) in online.iter().copied() {
+        let open = log
+            .sessions
+            .iter()
+            .any(|s| s.left.is_none() && is_session(s, server, player));
+        if !open {
+            log.sessions.push(Session {
+                username: player.clone(),
+                server: server.clone(),
+                joined: now,
+                left: None,
+            });
+        }
+    }
+    let date = DateTime::from_timestamp(now, 0)
+        .ok_or("invalid timestamp")?
+        .date_naive()
+        .to_string();
+    let peak = log.peaks.entry(date).or_insert(0);
+    *peak = count.max(*peak);
+    storage.save("sessions", &json!(log))
+}
+
+pub fn player_sessions(log: &SessionLog, username: &str) -> Vec<Session> {
+    log.sessions
+        .iter()
+        .filter(|s| s.username.eq_ignore_ascii_case(username))
+        .cloned()
+        .collect()
+}
+
+pub fn playtime(log: &SessionLog, username: &str) -> i64 {
+    let now = now();
+    log.sessions
+        .iter()
+        .filter(|s| s.username.eq_ignore_ascii_case(username))
+        .map(|s| s.left.unwrap_or(now) - s.joined)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_session(joined: i64) -> Session {
+        Session {
+            username: String::from("Steve"),
+            server: String::from("os-mc"),
+            joined,
+            left: None,
+        }
+    }
+
+    #[test]
+    fn api_downtime_is_not_playtime() {
+        let mut log = SessionLog {
+            sessions: vec![open_session(100)],
+            last_poll: Some(160),
+            ..SessionLog::default()
+        };
+        close_after_gap(&mut log, 210, 30);
+        assert_eq!(log.sessions[0].left, None);
+        close_after_gap(&mut log, 221, 30);
+        assert_eq!(log.sessions[0].left, Some(160));
+    }
+}
@@ -0,0 +1,262 @@
+//! Minecraft Java edition Server List Ping.
+//!
+//! Every packet is a VarInt length followed by a VarInt packet id and the
+//! packet body. A status exchange is a handshake with next state 1 and an
+//! empty status request, answered by a JSON string, then a ping carrying an
+//! i64 that the server echoes back in a pong.
+
+use crate::protocol::ServerStatus;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+const DEFAULT_PORT: u16 = 25565;
+const PROTOCOL_VERSION: i32 = -1;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_PACKET_LEN: usize = 256 * 1024;
+
+#[derive(Deserialize)]
+struct Response {
+    version: Option<Version>,
+    players: Option<Players>,
+    description: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Version {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Players {
+    max: u32,
+    online: u32,
+    #[serde(default)]
+    sample: Vec<Sample>,
+}
+
+#[derive(Deserialize)]
+struct Sample {
+    name: String,
+}
+
+pub fn write_varint(buffer: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    while value & !0x7f != 0 {
+        buffer.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+pub async fn read_varint(reader: &mut (impl AsyncRead + Unpin)) -> Result<i32, Box<dyn Error>> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let byte = reader.read_u8().await?;
+        value |= u32::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err("VarInt is longer than 5 bytes".into())
+}
+
+fn write_string(buffer: &mut Vec<u8>, value: &str) {
+    write_varint(buffer, value.len() as i32);
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+fn packet(id: i32, body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_varint(&mut data, id);
+    data.extend_from_slice(body);
+    let mut packet = Vec::new();
+    write_varint(&mut packet, data.len() as i32);
+    packet.extend_from_slice(&data);
+    packet
+}
+
+async fn read_packet(stream: &mut TcpStream) -> Result<(i32, Vec<u8>), Box<dyn Error>> {
+    let length = usize::try_from(read_varint(stream).await?)?;
+    if length == 0 || length > MAX_PACKET_LEN {
+        return Err(format!("packet length {} is out of range", length).into());
+    }
+    let mut data = vec![0; length];
+    stream.read_exact(&mut data).await?;
+    let mut body = &data[..];
+    let id = read_varint(&mut body).await?;
+    Ok((id, body.to_vec()))
+}
+
+fn chat_text(component: &Value) -> String {
+    match component {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts.iter().map(chat_text).collect(),
+        Value::Object(object) => {
+            let mut text = object
+                .get("text")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            if let Some(extra) = object.get("extra") {
+                text.push_str(&chat_text(extra));
+            }
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+fn split_address(address: &str) -> Result<(&str, u16), Box<dyn Error>> {
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, port.parse()?),
+        _ => (address, DEFAULT_PORT),
+    };
+    Ok((host.trim_start_matches('[').trim_end_matches(']'), port))
+}
+
+async fn exchange(
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+) -> Result<(ServerStatus, Duration), Box<dyn Error>> {
+    let mut handshake = Vec::new();
+    write_varint(&mut handshake, PROTOCOL_VERSION);
+    write_string(&mut handshake, host);
+    handshake.extend_from_slice(&port.to_be_bytes());
+    write_varint(&mut handshake, 1);
+    stream.write_all(&packet(0x00, &handshake)).await?;
+    stream.write_all(&packet(0x00, &[])).await?;
+    let (id, body) = read_packet(stream).await?;
+    if id != 0x00 {
+        return Err(format!("expected status response, got packet {:#04x}", id).into());
+    }
+    let mut body = &body[..];
+    let length = usize::try_from(read_varint(&mut body).await?)?;
+    let json = body.get(..length).ok_or("status response is truncated")?;
+    let response: Response = serde_json::from_slice(json)?;
+
+    let payload: i64 = rand::random();
+    let started = Instant::now();
+    stream
+        .write_all(&packet(0x01, &payload.to_be_bytes()))
+        .await?;
+    let (id, body) = read_packet(stream).await?;
+    if id != 0x01 || body != payload.to_be_bytes() {
+        return Err("server did not echo the ping payload".into());
+    }
+    let latency = started.elapsed();
+
+    // Servers only list a sample of the online players (vanilla sends at most
+    // 12), so `players` can be shorter than `online` on busy servers.
+    let players = response.players;
+    Ok((
+        ServerStatus {
+            players: players
+                .as_ref()
+                .map(|p| p.sample.iter().map(|s| s.name.clone()).collect())
+                .unwrap_or_default(),
+            online: players.as_ref().map(|p| p.online),
+            max_players: players.as_ref().map(|p| p.max),
+            motd: response.description.as_ref().map(chat_text),
+            version: response.version.map(|v| v.name),
+            ..ServerStatus::default()
+        },
+        latency,
+    ))
+}
+
+pub async fn ping(address: &str) -> Result<(ServerStatus, Duration), Box<dyn Error>> {
+    let (host, port) = split_address(address)?;
+    let mut stream = timeout(CONNECT_TIMEOUT, TcpStream::connect((host, port)))
+        .await
+        .map_err(|_| format!("timed out connecting to {}", address))??;
+    timeout(EXCHANGE_TIMEOUT, exchange(&mut stream, host, port))
+        .await
+        .map_err(|_| format!("timed out pinging {}", address))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn varints_round_trip() {
+        for (value, encoded) in [
+            (0, vec![0x00]),
+            (1, vec![0x01]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x01]),
+            (25565, vec![0xdd, 0xc7, 0x01]),
+            (i32::MAX, vec![0xff, 0xff, 0xff, 0xff, 0x07]),
+            (-1, vec![0xff, 0xff, 0xff, 0xff, 0x0f]),
+        ] {
+            let mut buffer = Vec::new();
+            write_varint(&mut buffer, value);
+            assert_eq!(buffer, encoded);
+            assert_eq!(read_varint(&mut &buffer[..]).await.unwrap(), value);
+        }
+        assert!(read_varint(&mut &[0xff; 6][..]).await.is_err());
+        assert!(read_varint(&mut &[0x80][..]).await.is_err());
+    }
+
+    async fn fake_server(status: &'static str, echo: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (id, handshake) = read_packet(&mut stream).await.unwrap();
+            assert_eq!(id, 0x00);
+            let mut expected = Vec::new();
+            write_varint(&mut expected, PROTOCOL_VERSION);
+            write_string(&mut expected, "127.0.0.1");
+            expected.extend_from_slice(&address.port().to_be_bytes());
+            write_varint(&mut expected, 1);
+            assert_eq!(handshake, expected);
+            assert_eq!(read_packet(&mut stream).await.unwrap(), (0x00, Vec::new()));
+            let mut body = Vec::new();
+            write_string(&mut body, status);
+            stream.write_all(&packet(0x00, &body)).await.unwrap();
+            let (id, mut payload) = read_packet(&mut stream).await.unwrap();
+            assert_eq!((id, payload.len()), (0x01, 8));
+            if !echo {
+                payload[0] ^= 0xff;
+            }
+            stream.write_all(&packet(0x01, &payload)).await.unwrap();
+        });
+        address.to_string()
+    }
+
+    #[tokio::test]
+    async fn ping_reads_status_and_echoes_payload() {
+        let address = fake_server(
+            r#"{"version":{"name":"1.21.4","protocol":769},
+                "players":{"max":100,"online":40,"sample":[{"name":"Steve","id":"0"}]},
+                "description":{"text":"Hello ","extra":[{"text":"world"}]}}"#,
+            true,
+        )
+        .await;
+        let (status, _) = ping(&address).await.unwrap();
+        assert_eq!(status.players, ["Steve"]);
+        assert_eq!(status.online, Some(40));
+        assert_eq!(status.max_players, Some(100));
+        assert_eq!(status.motd.as_deref(), Some("Hello world"));
+        assert_eq!(status.version.as_deref(), Some("1.21.4"));
+    }
+
+    #[tokio::test]
+    async fn ping_rejects_a_wrong_pong() {
+        let address = fake_server(r#"{"description":"motd"}"#, false).await;
+        assert!(ping(&address).await.is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{fetch_osm_info, protocol::ServerStatus, slp};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::RwLock,
+    time::{Duration, Instant, SystemTime},
+};
+
+fn now() -> i64 {
+    let now: DateTime<Utc> = SystemTime::now().into();
+    now.timestamp()
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusProtocol {
+    #[default]
+    Osmc,
+    Slp,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StatusTarget {
+    pub name: String,
+    pub address: String,
+    #[serde(default)]
+    pub protocol: StatusProtocol,
+}
+
+impl StatusTarget {
+    pub async fn fetch(&self) -> Result<(ServerStatus, Duration), Box<dyn Error>> {
+        match self.protocol {
+            StatusProtocol::Osmc => {
+                let started = Instant::now();
+                let status = fetch_osm_info(&self.address).await?;
+                Ok((status, started.elapsed()))
+            }
+            StatusProtocol::Slp => slp::ping(&self.address).await,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct CachedStatus {
+    pub status: ServerStatus,
+    pub last_updated: i64,
+    pub latency_ms: u64,
+    pub last_error: Option<String>,
+}
+
+impl CachedStatus {
+    pub fn age(&self) -> i64 {
+        now() - self.last_updated
+    }
+
+    /// Whether `players` is only a sample, as SLP servers send at most 12 names.
+    pub fn is_partial(&self) -> bool {
+        self.status
+            .online
+            .is_some_and(|online| online as usize > self.status.players.len())
+    }
+
+    /// `None` when the player is not in a partial sample, so they may still be
+    /// online.
+    pub fn is_online(&self, username: &str) -> Option<bool> {
+        if self
+            .status
+            .players
+            .iter()
+            .any(|player| player.eq_ignore_ascii_case(username))
+        {
+            Some(true)
+        } else if self.is_partial() {
+            None
+        } else {
+            Some(false)
+        }
+    }
+
+    pub fn is_stale(&self, interval: u32) -> bool {
+        self.last_error.is_some() || self.age() > 2 * i64::from(interval)
+    }
+}
+
+#[derive(Default)]
+pub struct StatusCache {
+    entries: RwLock<HashMap<String, CachedStatus>>,
+}
+
+impl StatusCache {
+    pub fn get(&self, name: &str) -> Option<CachedStatus> {
+        match self.entries.read() {
+            Ok(entries) => entries.get(name).cloned(),
+            Err(poisoned) => poisoned.into_inner().get(name).cloned(),
+        }
+    }
+
+    fn update(&self, update: impl FnOnce(&mut HashMap<String, CachedStatus>)) {
+        match self.entries.write() {
+            Ok(mut entries) => update(&mut entries),
+            Err(poisoned) => update(&mut poisoned.into_inner()),
+        }
+    }
+
+    pub fn retain(&self, targets: &[StatusTarget]) {
+        self.update(|entries| entries.retain(|name, _| targets.iter().any(|t| &t.name == name)));
+    }
+
+    pub async fn poll(&self, target: &StatusTarget) -> Result<(), Box<dyn Error>> {
+        match target.fetch().await {
+            Ok((status, latency)) => {
+                let latency_ms = latency.as_millis() as u64;
+                self.update(|entries| {
+                    entries.insert(
+                        target.name.clone(),
+                        CachedStatus {
+                            status,
+                            last_updated: now(),
+                            latency_ms,
+                            last_error: None,
+                        },
+                    );
+                });
+                Ok(())
+            }
+            Err(e) => {
+                self.update(|entries| {
+                    if let Some(entry) = entries.get_mut(&target.name) {
+                        entry.last_error = Some(e.to_string());
+                    }
+                });
+                Err(e)
+            }
+        }
+    }
+}
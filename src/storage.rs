@@ -0,0 +1,182 @@
+use crate::{backup, read_json_from_file, write_json_to_file};
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{error::Error, sync::Mutex, time::SystemTime};
+use xdg::BaseDirectories;
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    File,
+    Sqlite,
+}
+
+pub trait Storage: Send + Sync {
+    fn load(&self, name: &str) -> Result<Option<Value>, Box<dyn Error>>;
+    fn save(&self, name: &str, json: &Value) -> Result<(), Box<dyn Error>>;
+    fn update(&self, name: &str, before: &Value, after: &Value) -> Result<(), Box<dyn Error>>;
+    fn backups(&self) -> Result<Vec<i64>, Box<dyn Error>>;
+    fn load_backup(&self, timestamp: i64) -> Result<Option<Value>, Box<dyn Error>>;
+}
+
+pub struct FileStorage;
+
+impl Storage for FileStorage {
+    fn load(&self, name: &str) -> Result<Option<Value>, Box<dyn Error>> {
+        let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
+        match xdg_dirs.find_data_file(format!("{}.json", name)) {
+            Some(path) => Ok(Some(read_json_from_file(&path)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, name: &str, json: &Value) -> Result<(), Box<dyn Error>> {
+        let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
+        let path = xdg_dirs.place_data_file(format!("{}.json", name))?;
+        write_json_to_file(&mut json.clone(), &path)
+    }
+
+    fn update(&self, name: &str, before: &Value, after: &Value) -> Result<(), Box<dyn Error>> {
+        backup(&mut before.clone())?;
+        self.save(name, after)
+    }
+
+    fn backups(&self) -> Result<Vec<i64>, Box<dyn Error>> {
+        let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
+        let mut timestamps: Vec<i64> = xdg_dirs
+            .list_data_files_once("")
+            .iter()
+            .filter_map(|path| path.file_name()?.to_str())
+            .filter_map(|name| name.strip_prefix("data-")?.strip_suffix(".json"))
+            .filter_map(|timestamp| timestamp.parse().ok())
+            .collect();
+        timestamps.sort();
+        Ok(timestamps)
+    }
+
+    fn load_backup(&self, timestamp: i64) -> Result<Option<Value>, Box<dyn Error>> {
+        self.load(&format!("data-{}", timestamp))
+    }
+}
+
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open() -> Result<SqliteStorage, Box<dyn Error>> {
+        let xdg_dirs = BaseDirectories::with_prefix("nameful-api");
+        let db_path = xdg_dirs.place_data_file("nameful.db")?;
+        let connection = Connection::open(&db_path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS documents (name TEXT PRIMARY KEY, body TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS backups (timestamp INTEGER NOT NULL, body TEXT NOT NULL);",
+        )?;
+        let storage = SqliteStorage {
+            connection: Mutex::new(connection),
+        };
+        for name in ["data", "nick-cache", "keys", "nicknames", "sessions"] {
+            if storage.load(name)?.is_none()
+                && let Some(json) = FileStorage.load(name)?
+            {
+                storage.save(name, &json)?;
+            }
+        }
+        if storage.backups()?.is_empty() {
+            let connection = storage.connection.lock().map_err(|e| e.to_string())?;
+            for timestamp in FileStorage.backups()? {
+                if let Some(json) = FileStorage.load_backup(timestamp)? {
+                    connection.execute(
+                        "INSERT INTO backups (timestamp, body) VALUES (?1, ?2)",
+                        params![timestamp, serde_json::to_string(&json)?],
+                    )?;
+                }
+            }
+        }
+        Ok(storage)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self, name: &str) -> Result<Option<Value>, Box<dyn Error>> {
+        let connection = self.connection.lock().map_err(|e| e.to_string())?;
+        let body: Option<String> = connection
+            .query_row(
+                "SELECT body FROM documents WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match body {
+            Some(body) => Ok(Some(serde_json::from_str(&body)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, name: &str, json: &Value) -> Result<(), Box<dyn Error>> {
+        let connection = self.connection.lock().map_err(|e| e.to_string())?;
+        connection.execute(
+            "INSERT INTO documents (name, body) VALUES (?1, ?2)
+             ON CONFLICT(name) DO UPDATE SET body = excluded.body",
+            params![name, serde_json::to_string(json)?],
+        )?;
+        Ok(())
+    }
+
+    fn update(&self, name: &str, before: &Value, after: &Value) -> Result<(), Box<dyn Error>> {
+        let now: DateTime<Utc> = SystemTime::now().into();
+        let mut connection = self.connection.lock().map_err(|e| e.to_string())?;
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current: Option<String> = transaction
+            .query_row(
+                "SELECT body FROM documents WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(current) = current
+            && serde_json::from_str::<Value>(&current)? != *before
+        {
+            return Err(format!("{} was changed by another writer", name).into());
+        }
+        transaction.execute(
+            "INSERT INTO backups (timestamp, body) VALUES (?1, ?2)",
+            params![now.timestamp(), serde_json::to_string(before)?],
+        )?;
+        transaction.execute(
+            "INSERT INTO documents (name, body) VALUES (?1, ?2)
+             ON CONFLICT(name) DO UPDATE SET body = excluded.body",
+            params![name, serde_json::to_string(after)?],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn backups(&self) -> Result<Vec<i64>, Box<dyn Error>> {
+        let connection = self.connection.lock().map_err(|e| e.to_string())?;
+        let mut statement =
+            connection.prepare("SELECT DISTINCT timestamp FROM backups ORDER BY timestamp")?;
+        let timestamps = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+        Ok(timestamps)
+    }
+
+    fn load_backup(&self, timestamp: i64) -> Result<Option<Value>, Box<dyn Error>> {
+        let connection = self.connection.lock().map_err(|e| e.to_string())?;
+        let body: Option<String> = connection
+            .query_row(
+                "SELECT body FROM backups WHERE timestamp = ?1 ORDER BY rowid DESC LIMIT 1",
+                params![timestamp],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match body {
+            Some(body) => Ok(Some(serde_json::from_str(&body)?)),
+            None => Ok(None),
+        }
+    }
+}